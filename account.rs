@@ -1,218 +1,648 @@
 extern crate exonum_rocksdb;
 extern crate bincode;
 
+use std::collections::BTreeMap;
 use std::result::Result;
-use self::exonum_rocksdb::{DB, WriteBatch};
+use self::exonum_rocksdb::WriteBatch;
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::{self, account_key, meta_key};
+use super::lock::lock_keys;
+use super::token_set::{TokenSet, balance_from_bytes};
+use super::owner_index;
+use super::journal::{self, JournalKind};
+use super::auth::{self, TransferAuth};
+use super::allowance;
+use super::freeze::check_not_frozen;
+use super::fee;
+use super::registry::check_registered;
 
 //get balance for account.
-pub fn get_balance(db: &DB, account: &String) -> Result<usize, &'static str> {
+pub fn get_balance(db: &Ledger, asset: &String, account: &String) -> Result<usize, LedgerError> {
+    keys::check_asset(asset)?;
+
     if account.len() == 0 {
-        return Err("invalide account id.");
+        return Err(LedgerError::InvalidInput("empty account id"));
     }
-	
-    if get_super_account_id(db).unwrap() == *account {
-        match get_super_account_value(db, "SuperBalance") {
-            Ok(value) => {
-                return Ok(value);
-            },
-            Err(e) => {
-                println!("Got error: {:?}", e);
-                return Err("get_super_account_value error.");
-            },
-        }
+
+    if get_super_account_id(db, asset)? == *account {
+        return get_super_account_value(db, asset, keys::SUPER_BALANCE);
     }
 
-    unsafe {
-    	let mut iter = db.raw_iterator();
-    	iter.seek(&account.as_bytes());
-    	if iter.valid() {
-            let len: usize = bincode::serde::deserialize(&iter.value_inner().unwrap()[0..8]).unwrap();
+    //exact key lookup, a missing account must not fall through to its neighbour.
+    match db.get(&account_key(asset, account))? {
+        Some(value) => {
+            let len: usize = balance_from_bytes(&value)?;
             println!("get '{}' balance: {}", account,len);
-            return Ok(len);
-        }
-        return Err("Account not found!");
+            Ok(len)
+        },
+        None => Err(LedgerError::AccountNotFound(account.clone())),
     }
 }
 
-//to do use result as return code sets for better code.
-pub fn transfer(db: &DB, from: &String, to: &String, amount: usize) -> Result<(), &'static str> {
-    
+//who owns token_id, None if the id was never issued.
+pub fn owner_of(db: &Ledger, asset: &String, token_id: usize) -> Result<Option<String>, LedgerError> {
+    keys::check_asset(asset)?;
+
+    if let Some(owner) = owner_index::lookup(db, asset, token_id)? {
+        return Ok(Some(owner));
+    }
+
+    //ids not rolled out yet are the tail block still held by super account, burned ids have no owner.
+    let super_balance: usize = get_super_account_value(db, asset, keys::SUPER_BALANCE)?;
+    let issued_end = get_super_account_value(db, asset, keys::TOTAL_SUPPLY)? + get_burned(db, asset)?;
+    if token_id >= issued_end - super_balance && token_id < issued_end {
+        return Ok(Some(get_super_account_id(db, asset)?));
+    }
+
+    Ok(None)
+}
+
+//a client transaction id makes the transfer idempotent: a retry with the same id returns the original outcome
+//instead of moving tokens again. a sender with a registered public key must sign the transfer.
+pub fn transfer(db: &Ledger, asset: &String, from: &String, to: &String, amount: usize, tx_id: Option<&String>,
+                signed: Option<&TransferAuth>) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
+
     if from.len() == 0 {
-        return Err("Wrong sender id!");
+        return Err(LedgerError::InvalidInput("empty sender id"));
     }
 
     if to.len() == 0 {
-        return Err("Wrong receiver id!");
+        return Err(LedgerError::InvalidInput("empty receiver id"));
     }
 
     if amount == 0 {
-        return Err("Invalid amount to transfer!");
+        return Err(LedgerError::InvalidInput("amount to transfer must be greater than zero"));
+    }
+
+    let super_id = get_super_account_id(db, asset)?;
+    if super_id == *to {
+        return Err(LedgerError::InvalidInput("transfer to super account is not allowed"));
     }
 
-    //try to write below nested match more readable in rust.
-    match get_super_account_id(db) {
-        Ok(super_id) => {
-            if super_id == *to {
-                return Err("transfer to super account is not allowed.");
+    //the id stays locked until the transfer is written, so a concurrent retry cannot apply it twice.
+    let _tx_lock = match tx_id {
+        Some(tx_id) => {
+            if tx_id.len() == 0 {
+                return Err(LedgerError::InvalidInput("empty transaction id"));
             }
 
-            if super_id == *from {
-                match rollout_tokens(db, to, amount) {
-                    Ok(()) => {
-                        return Ok(());
-                    },
-                    Err(e) => {
-                        println!("Got erro: {:?}", e);
-                        return Err("rollout_tokens failed.");
-                    },
+            let tx_lock = lock_keys(db, vec![keys::tx_id_key(asset, tx_id)])?;
+            if let Some(record) = journal::find_tx(db, asset, tx_id)? {
+                if record.from != *from || record.to != *to || record.amount != amount {
+                    return Err(LedgerError::InvalidInput("transaction id already used by another transfer"));
                 }
+                println!("Transaction '{}' was applied already as #{}, skip it.", tx_id, record.seq);
+                return Ok(());
             }
+            Some(tx_lock)
+        },
+        None => None,
+    };
 
-            match end_user_transfer(db, from, to, amount) {
-                Ok(()) => {
-                    return Ok(());
-                },
-                Err(e) => {
-                    println!("Got error: {:?}", e);
-                    return Err("end_user_transfer failed");
+    //the nonce is read then saved with the transfer, two transfers of the same sender must not both pass the check.
+    let _nonce_lock = lock_keys(db, vec![keys::nonce_key(asset, from)])?;
+    let extras = TransferExtras {
+        tx_id: tx_id,
+        nonce: auth::check_transfer(db, asset, from, to, amount, signed)?,
+        allowance: None,
+        fee: None,
+    };
+
+    if super_id == *from {
+        return rollout_tokens(db, asset, from, to, amount, &extras);
+    }
+
+    end_user_transfer(db, asset, from, to, amount, extras)
+}
+
+//transfer exactly the listed token ids between end-user's accounts, the sender must own every one of them.
+pub fn transfer_tokens(db: &Ledger, asset: &String, from: &String, to: &String, token_ids: &[usize]) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
+
+    if from.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty sender id"));
+    }
+
+    if to.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty receiver id"));
+    }
+
+    if token_ids.len() == 0 {
+        return Err(LedgerError::InvalidInput("no token id to transfer"));
+    }
+
+    //super account always holds the tail block of ids, picking ids out of it would leave holes.
+    let super_id = get_super_account_id(db, asset)?;
+    if super_id == *from {
+        return Err(LedgerError::InvalidInput("use transfer to roll out tokens from super account"));
+    }
+
+    if super_id == *to {
+        return Err(LedgerError::InvalidInput("transfer to super account is not allowed"));
+    }
+
+    //there is no signed variant of it yet, so it cannot spend from an account with a public key.
+    auth::check_unsigned(db, asset, from)?;
+
+    let _locks = lock_keys(db, transfer_lock_keys(db, asset, from, to)?)?;
+    check_not_frozen(db, asset, from)?;
+    check_not_frozen(db, asset, to)?;
+    check_registered(db, asset, to)?;
+
+    let mut sender_balance = load_account(db, asset, from)?;
+    let tx_tokens = take_token_ids(&mut sender_balance, from, token_ids)?;
+    let extras = TransferExtras {
+        fee: fee::take_fee(db, asset, from, &mut sender_balance, tx_tokens.len())?,
+        ..TransferExtras::default()
+    };
+
+    println!("Transfering from '{}' to '{}' with token ids {:?}", from, to, tx_tokens.ranges());
+    commit_end_user_transfer(db, asset, from, to, &sender_balance, &tx_tokens, &extras)
+}
+
+//move tokens of many (from, to, amount) legs in one atomic batch, legs are applied in order so a leg can spend
+//what an earlier one received. if any leg is invalid nothing is written.
+pub fn transfer_batch(db: &Ledger, asset: &String, legs: &[(String, String, usize)]) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
+
+    if legs.len() == 0 {
+        return Err(LedgerError::InvalidInput("no leg to transfer"));
+    }
+
+    let super_id = get_super_account_id(db, asset)?;
+    let mut lock_list: Vec<Vec<u8>> = Vec::new();
+    for &(ref from, ref to, amount) in legs {
+        if from.len() == 0 {
+            return Err(LedgerError::InvalidInput("empty sender id"));
+        }
+
+        if to.len() == 0 {
+            return Err(LedgerError::InvalidInput("empty receiver id"));
+        }
+
+        if amount == 0 {
+            return Err(LedgerError::InvalidInput("amount to transfer must be greater than zero"));
+        }
+
+        if super_id == *to {
+            return Err(LedgerError::InvalidInput("transfer to super account is not allowed"));
+        }
+
+        check_registered(db, asset, to)?;
+
+        //legs carry no signature.
+        auth::check_unsigned(db, asset, from)?;
+
+        if super_id == *from {
+            lock_list.push(meta_key(asset, keys::SUPER_BALANCE));
+        } else {
+            lock_list.push(account_key(asset, from));
+        }
+        lock_list.push(account_key(asset, to));
+    }
+
+    //fees of end user legs go to the collector, which is written and counted like any other account.
+    let fees = fee::fee_schedule(db, asset)?;
+    if let Some(ref fees) = fees {
+        lock_list.push(account_key(asset, &fees.collector));
+        lock_list.push(meta_key(asset, keys::FEES_COLLECTED));
+    }
+
+    let lock_super = legs.iter().any(|leg| leg.0 == super_id);
+    let _locks = lock_keys(db, lock_list)?;
+    if lock_super {
+        check_super_account(db, asset, &super_id)?;
+    }
+
+    //working copy of every end user account the batch touches, None if it does not exist yet.
+    let mut initial: BTreeMap<String, Option<TokenSet>> = BTreeMap::new();
+    for &(ref from, ref to, _) in legs {
+        for account in &[from, to] {
+            if **account != super_id && !initial.contains_key(*account) {
+                check_not_frozen(db, asset, account)?;
+                let balance = match load_account(db, asset, account) {
+                    Ok(balance) => Some(balance),
+                    Err(LedgerError::AccountNotFound(_)) => None,
+                    Err(e) => return Err(e),
+                };
+                initial.insert((*account).clone(), balance);
+            }
+        }
+    }
+    if let Some(ref fees) = fees {
+        if !initial.contains_key(&fees.collector) {
+            let balance = match load_account(db, asset, &fees.collector) {
+                Ok(balance) => Some(balance),
+                Err(LedgerError::AccountNotFound(_)) => None,
+                Err(e) => return Err(e),
+            };
+            initial.insert(fees.collector.clone(), balance);
+        }
+    }
+    let mut balances = initial.clone();
+    let mut fees_paid: usize = 0;
+
+    let mut super_balance: usize = get_super_account_value(db, asset, keys::SUPER_BALANCE)?;
+    let issued_end = get_super_account_value(db, asset, keys::TOTAL_SUPPLY)? + get_burned(db, asset)?;
+
+    let mut batch = WriteBatch::default();
+    let mut journal = journal::begin(db)?;
+    for &(ref from, ref to, amount) in legs {
+        let (tx_tokens, fee) = if super_id == *from {
+            if super_balance < amount {
+                return Err(LedgerError::InsufficientBalance { account: super_id.clone(), balance: super_balance, requested: amount });
+            }
+            let start_id = issued_end - super_balance;
+            super_balance -= amount;
+            (TokenSet::from_range(start_id, start_id + amount), None)
+        } else {
+            match balances.get_mut(from).unwrap() {
+                &mut Some(ref mut balance) => {
+                    let tx_tokens = take_amount(balance, from, amount)?;
+                    let fee = fee::take_fee(db, asset, from, balance, amount)?;
+                    (tx_tokens, fee)
                 },
+                &mut None => return Err(LedgerError::AccountNotFound(from.clone())),
             }
+        };
 
-        },
-        Err(e) => {
-            println!("Got error: {:?}", e);
-            return Err("Get super account id failed.");
-        },
+        let kind = if super_id == *from { JournalKind::Rollout } else { JournalKind::Transfer };
+        journal.append(&mut batch, kind, asset, from, to, &tx_tokens, None)?;
+        add_tokens(&mut balances, to, &tx_tokens);
+
+        if let Some((collector, fee_tokens)) = fee {
+            check_not_frozen(db, asset, &collector)?;
+            journal.append(&mut batch, JournalKind::Fee, asset, from, &collector, &fee_tokens, None)?;
+            add_tokens(&mut balances, &collector, &fee_tokens);
+            fees_paid += fee_tokens.len();
+        }
+    }
+
+    //every touched account is locked, so its final set is written as is. the owner index only follows the net
+    //change of each account, tokens passed along a chain of legs are indexed to where they end up.
+    let mut gained_list: Vec<(String, TokenSet)> = Vec::new();
+    for (account, balance) in &balances {
+        let balance = match *balance {
+            Some(ref balance) => balance,
+            None => continue,
+        };
+        batch.put(&account_key(asset, account), &balance.to_bytes())?;
+
+        let mut gained = balance.clone();
+        if let Some(ref before) = initial[account] {
+            for &(start, end) in before.ranges() {
+                gained.remove_range(start, end);
+            }
+
+            let mut lost = before.clone();
+            for &(start, end) in balance.ranges() {
+                lost.remove_range(start, end);
+            }
+            owner_index::unindex(db, &mut batch, asset, account, &lost)?;
+        }
+        gained_list.push((account.clone(), gained));
+    }
+    for (account, gained) in gained_list {
+        owner_index::index_owned(&mut batch, asset, &gained, &account)?;
+    }
+    //super balance is only locked when a leg rolls out.
+    if lock_super {
+        batch.put(&meta_key(asset, keys::SUPER_BALANCE), &usize_bytes(super_balance))?;
     }
+    if fees_paid > 0 {
+        batch.put(&meta_key(asset, keys::FEES_COLLECTED), &usize_bytes(fee::fees_collected(db, asset)? + fees_paid))?;
+    }
+    db.write(batch)?;
+
+    println!("Batch of {} transfers done, {} tokens paid as fees.", legs.len(), fees_paid);
+    Ok(())
 }
 
-fn get_super_account_id(db: &DB) -> Result<String, &'static str> {
-    match db.get(b"SuperAccount") {
-        Ok(Some(value)) => {
-            let super_id = value.to_utf8().unwrap().to_string();
-            Ok(super_id)
-        },
-        Ok(None) => {
-            return Err("the database haven't initailized with super account and total supply.");
-        },
-        Err(e) => {
-            println!("Got error: {:?}", e);
-            return Err("operational problem encountered.");
+fn add_tokens(balances: &mut BTreeMap<String, Option<TokenSet>>, account: &String, tokens: &TokenSet) {
+    let balance = balances.get_mut(account).unwrap();
+    if balance.is_none() {
+        *balance = Some(TokenSet::new());
+    }
+    balance.as_mut().unwrap().union(tokens);
+}
+
+//written in the same batch as the transfer they come with.
+#[derive(Default)]
+pub(crate) struct TransferExtras<'a> {
+    pub tx_id: Option<&'a String>,
+    //nonce of a signed transfer, saved as the sender's last used one.
+    pub nonce: Option<usize>,
+    //spender of a transfer_from and the allowance it has left afterwards.
+    pub allowance: Option<(&'a String, usize)>,
+    //fee collector and the fee tokens taken from the sender.
+    pub fee: Option<(String, TokenSet)>,
+}
+
+//keys an end user transfer holds: both accounts and the fee collector, which gets tokens merged in as well.
+pub(crate) fn transfer_lock_keys(db: &Ledger, asset: &String, from: &String, to: &String) -> Result<Vec<Vec<u8>>, LedgerError> {
+    let mut lock_list = vec![account_key(asset, from), account_key(asset, to)];
+    if let Some(fees) = fee::fee_schedule(db, asset)? {
+        lock_list.push(account_key(asset, &fees.collector));
+    }
+    Ok(lock_list)
+}
+
+pub(crate) fn get_super_account_id(db: &Ledger, asset: &String) -> Result<String, LedgerError> {
+    match db.get(&meta_key(asset, keys::SUPER_ACCOUNT))? {
+        Some(value) => {
+            match value.to_utf8() {
+                Some(super_id) => Ok(super_id.to_string()),
+                None => Err(LedgerError::Codec("super account id")),
+            }
         },
+        None => Err(LedgerError::NotInitialized),
     }
 }
 
-fn get_super_account_value(db: &DB, key: &str) -> Result<usize, &'static str>  {
-	
-    match db.get(key.as_bytes()) {
-        Ok(Some(bytes)) =>{
-            let mut value: usize= bincode::serde::deserialize(&bytes).unwrap();
+//super account may be rotated while a caller waits for the super balance lock, so the id checked before
+//taking it is checked again. caller must hold the lock of super balance.
+pub(crate) fn check_super_account(db: &Ledger, asset: &String, account: &String) -> Result<(), LedgerError> {
+    if get_super_account_id(db, asset)? != *account {
+        return Err(LedgerError::PermissionDenied(account.clone()));
+    }
+    Ok(())
+}
+
+pub(crate) fn get_super_account_value(db: &Ledger, asset: &String, key: &str) -> Result<usize, LedgerError>  {
+    match db.get(&meta_key(asset, key))? {
+        Some(bytes) => {
+            let value: usize = match bincode::serde::deserialize(&bytes) {
+                Ok(value) => value,
+                Err(_) => return Err(LedgerError::Codec("super account meta")),
+            };
             println!("get {}: {}", key, value);
             Ok(value)
         },
-        Ok(None) => {
-            return Err("super account meta is not found from db!");   
-        }
-        Err(e) => {
-            println!("Got error: {:?}", e);
-            return Err("rollout_tokens operational problem encountered.");
+        None => Err(LedgerError::NotInitialized),
+    }
+}
+
+//number of tokens ever burned, ledgers initialized before burning existed have none.
+pub(crate) fn get_burned(db: &Ledger, asset: &String) -> Result<usize, LedgerError> {
+    match db.get(&meta_key(asset, keys::BURNED))? {
+        Some(_) => get_super_account_value(db, asset, keys::BURNED),
+        None => Ok(0),
+    }
+}
+
+pub(crate) fn usize_bytes(value: usize) -> Vec<u8> {
+    bincode::serde::serialize(&value, bincode::SizeLimit::Infinite).unwrap()
+}
+
+pub(crate) fn load_account(db: &Ledger, asset: &String, account: &String) -> Result<TokenSet, LedgerError> {
+    match db.get(&account_key(asset, account))? {
+        Some(value) => TokenSet::from_bytes(&value),
+        None => Err(LedgerError::AccountNotFound(account.clone())),
+    }
+}
+
+//take the last `amount` tokens out of an account's set, the last range might be cut in two.
+pub(crate) fn take_amount(balance: &mut TokenSet, account: &String, amount: usize) -> Result<TokenSet, LedgerError> {
+    match balance.split_off_last(amount) {
+        Some(tokens) => Ok(tokens),
+        None => {
+            Err(LedgerError::InsufficientBalance {
+                account: account.clone(),
+                balance: balance.len(),
+                requested: amount,
+            })
         },
     }
 }
 
+//take exactly the listed ids out of an account's set, the account must own every one of them.
+pub(crate) fn take_token_ids(balance: &mut TokenSet, account: &String, token_ids: &[usize]) -> Result<TokenSet, LedgerError> {
+    let tokens = TokenSet::from_ids(token_ids)?;
+    if tokens.len() != token_ids.len() {
+        return Err(LedgerError::InvalidInput("duplicated token id"));
+    }
+
+    for &id in token_ids {
+        if !balance.contains(id) {
+            return Err(LedgerError::TokenNotOwned { account: account.clone(), token_id: id });
+        }
+    }
+
+    for &(start, end) in tokens.ranges() {
+        balance.remove_range(start, end);
+    }
+    Ok(tokens)
+}
+
 //roll out tokens from super account to end user account.
-fn rollout_tokens(db: &DB, to: &String, amount: usize) -> Result<(), &'static str> {
+fn rollout_tokens(db: &Ledger, asset: &String, super_id: &String, to: &String, amount: usize, extras: &TransferExtras) -> Result<(), LedgerError> {
 	
+    //hold super balance and receiver until the batch is written, so concurrent roll outs cannot hand out the same ids.
+    let _locks = lock_keys(db, vec![meta_key(asset, keys::SUPER_BALANCE), account_key(asset, to)])?;
+    check_super_account(db, asset, super_id)?;
+    check_not_frozen(db, asset, to)?;
+    check_registered(db, asset, to)?;
+
     //get super account meta from db.
-    let super_balance: usize = get_super_account_value(db, "SuperBalance").unwrap();
-    let super_total_supply: usize = get_super_account_value(db, "TotalSupply").unwrap();
+    let super_balance: usize = get_super_account_value(db, asset, keys::SUPER_BALANCE)?;
+    let super_total_supply: usize = get_super_account_value(db, asset, keys::TOTAL_SUPPLY)?;
+    let burned: usize = get_burned(db, asset)?;
 
     if super_balance < amount {
-        return Err("super does not have enough balance to roll out.");
+        return Err(LedgerError::InsufficientBalance {
+            account: super_id.clone(),
+            balance: super_balance,
+            requested: amount,
+        });
     }
 	
-    //to do roll out:
-    let start_id = super_total_supply - super_balance;
+    //ids are never reused: issued ids are [0, TotalSupply + Burned) and super account holds the tail block of them.
+    let start_id = super_total_supply + burned - super_balance;
     let end_id = start_id + amount;
-	
-    //below could be improved since the heap could not hold a big array.
-    let mut token_ids: Vec<usize> = Vec::new();
-    token_ids.reserve(amount);
-
-    for id in start_id .. end_id {
-        token_ids.push(id);
-    }
 
-    let bytes = bincode::serde::serialize(&token_ids, bincode::SizeLimit::Infinite).unwrap();
+    //a contiguous block of ids is a single range, no matter how big the amount is.
+    let tokens = TokenSet::from_range(start_id, end_id);
 
     println!("Going to roll out {} from super account to end user: '{}'", amount, to);
-    //atomic commit: update of super balance, end-user's balance array.
+    //atomic commit: update of super balance, end-user's balance array and the journal.
     let mut batch = WriteBatch::default();
-    let new_balance = super_balance - amount;
-    let balance_bytes = bincode::serde::serialize(&new_balance, bincode::SizeLimit::Infinite).unwrap();
-    let _ = batch.put(b"SuperBalance", &balance_bytes);
-    let _ = batch.merge(&to.as_bytes(), &bytes);
-
-    match db.write(batch) {
-        Ok(_) => {
-            println!("Roll out tokens done.");
-        },
-        Err(e) => {
-            println!("Got error: {:?}", e);
-            return Err("operational problem encountered.");
-        },
+    batch.put(&meta_key(asset, keys::SUPER_BALANCE), &usize_bytes(super_balance - amount))?;
+    batch.merge(&account_key(asset, to), &tokens.to_bytes())?;
+    owner_index::index_rollout(&mut batch, asset, start_id, end_id, to)?;
+    if let Some(nonce) = extras.nonce {
+        auth::put_nonce(&mut batch, asset, super_id, nonce)?;
     }
+    let _seq_lock = journal::append(db, &mut batch, JournalKind::Rollout, asset, super_id, to, &tokens, extras.tx_id)?;
+    db.write(batch)?;
+
+    println!("Roll out tokens done.");
     Ok(())
 }
 
 //transfer tokens between end-user's accounts.
-fn end_user_transfer(db: &DB, from: &String, to: &String, amount: usize) -> Result<(), &'static str> {
+fn end_user_transfer(db: &Ledger, asset: &String, from: &String, to: &String, amount: usize, mut extras: TransferExtras) -> Result<(), LedgerError> {
     
-    let mut sender_balance: Vec<usize>;
-    //in production, below get and update should be controlled as atomic operation.
-    //get sender's account balance.
-    match db.get(&from.as_bytes()) {
-        Ok(Some(value)) =>{
-            sender_balance = bincode::serde::deserialize(&value).unwrap();
-        },
-        Ok(None) => {
-            return Err("tansfer token, cannot find sender account from db.");
-        },
-        Err(e) => {
-            println!("Got error: {:?}", e);
-            return Err("operational problem encountered.");
-        },
-    }
+    //get and update are serialized per account: sender is read then overwritten, and a merge into the receiver
+    //must not land between a concurrent get and put of the same account.
+    let _locks = lock_keys(db, transfer_lock_keys(db, asset, from, to)?)?;
+    check_not_frozen(db, asset, from)?;
+    check_not_frozen(db, asset, to)?;
+    check_registered(db, asset, to)?;
 
-    if sender_balance.len() < amount {
-        return Err("The sender does not have enough token!");
-    }
-
-    //split into to 2 vectors.
-    let index = sender_balance.len() - amount;
-    let tx_tokens = sender_balance.split_off(index);
+    //get sender's account balance and split it into to 2 token sets, the fee comes out of what is left.
+    let mut sender_balance = load_account(db, asset, from)?;
+    let tx_tokens = take_amount(&mut sender_balance, from, amount)?;
+    extras.fee = fee::take_fee(db, asset, from, &mut sender_balance, amount)?;
 
     println!("Transfering from '{}' to '{}' with {} tokens", from, to, amount);
+    commit_end_user_transfer(db, asset, from, to, &sender_balance, &tx_tokens, &extras)
+}
 
-    let sender_balance_bytes = bincode::serde::serialize(&sender_balance, bincode::SizeLimit::Infinite).unwrap();
-    let tx_tokens_bytes = bincode::serde::serialize(&tx_tokens, bincode::SizeLimit::Infinite).unwrap();
-
-    //commit to db.
+//atomic commit: overwrite sender's remaining tokens, merge the moved ones into receiver and the fee into its collector,
+//re-point them in the owner index and journal the transfer.
+//caller must hold the locks of both accounts, of the fee collector and of the extras given.
+pub(crate) fn commit_end_user_transfer(db: &Ledger, asset: &String, from: &String, to: &String, sender_balance: &TokenSet, tx_tokens: &TokenSet, extras: &TransferExtras) -> Result<(), LedgerError> {
     let mut batch = WriteBatch::default();
-    let _ = batch.put(&from.as_bytes(), &sender_balance_bytes);
-    let _ = batch.merge(&to.as_bytes(), &tx_tokens_bytes);
-    
-    match db.write(batch) {
-        Ok(_) => {
-            println!("'{}' have {} tokens left, {} tokens to be transfered.", from, sender_balance.len(), tx_tokens.len());
-        },
-        Err(e) => {
-            println!("Got error: {:?}", e);
-            return Err("operational problem encountered");
+    batch.put(&account_key(asset, from), &sender_balance.to_bytes())?;
+    batch.merge(&account_key(asset, to), &tx_tokens.to_bytes())?;
+
+    //one unindex for everything leaving the sender, two of them would both rewrite the entries they share.
+    let mut moved = tx_tokens.clone();
+    if let Some((_, ref fee_tokens)) = extras.fee {
+        moved.union(fee_tokens);
+    }
+    owner_index::unindex(db, &mut batch, asset, from, &moved)?;
+    owner_index::index_owned(&mut batch, asset, tx_tokens, to)?;
+
+    let _fee_lock = match extras.fee {
+        Some((ref collector, ref fee_tokens)) => {
+            //the collector is locked by transfer_lock_keys, a frozen one cannot receive fees either.
+            check_not_frozen(db, asset, collector)?;
+            batch.merge(&account_key(asset, collector), &fee_tokens.to_bytes())?;
+            owner_index::index_owned(&mut batch, asset, fee_tokens, collector)?;
+            Some(fee::count_fee(db, &mut batch, asset, fee_tokens.len())?)
         },
+        None => None,
+    };
+
+    if let Some(nonce) = extras.nonce {
+        auth::put_nonce(&mut batch, asset, from, nonce)?;
+    }
+    if let Some((spender, remaining)) = extras.allowance {
+        allowance::put_allowance(&mut batch, asset, from, spender, remaining)?;
+    }
+
+    let mut journal = journal::begin(db)?;
+    journal.append(&mut batch, JournalKind::Transfer, asset, from, to, tx_tokens, extras.tx_id)?;
+    if let Some((ref collector, ref fee_tokens)) = extras.fee {
+        journal.append(&mut batch, JournalKind::Fee, asset, from, collector, fee_tokens, None)?;
     }
+    db.write(batch)?;
 
+    println!("'{}' have {} tokens left, {} tokens to be transfered.", from, sender_balance.len(), tx_tokens.len());
+    if let Some((ref collector, ref fee_tokens)) = extras.fee {
+        println!("'{}' paid a fee of {} tokens to '{}'.", from, fee_tokens.len(), collector);
+    }
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::sync::Arc;
+    use std::thread;
+    use self::tempdir::TempDir;
+    use super::{get_balance, transfer, transfer_batch, load_account};
+    use super::super::ledger::{open_ledger, Ledger};
+    use super::super::config::DbConfig;
+    use super::super::error::LedgerError;
+    use super::super::keys::account_key;
+    use super::super::lock::lock_keys;
+    use super::super::bootstrap::init_super_account;
+    use super::super::owner_index;
+    use super::super::audit::audit;
+
+    fn setup(dir: &TempDir) -> Ledger {
+        let db = open_ledger(dir.path().to_str().unwrap(), &DbConfig::default()).unwrap();
+        init_super_account(&db, &"default".to_string(), &"S".to_string(), 1000, None).unwrap();
+        db
+    }
+
+    #[test]
+    fn concurrent_transfers_from_one_sender_never_double_spend() {
+        let dir = TempDir::new("double_spend").unwrap();
+        let db = Arc::new(setup(&dir));
+        let asset = "default".to_string();
+        transfer(&db, &asset, &"S".to_string(), &"a".to_string(), 10, None, None).unwrap();
 
+        //every thread wants 3 of the 10 tokens, only 3 of them can get them.
+        let threads: Vec<_> = (0..8).map(|i| {
+            let db = db.clone();
+            thread::spawn(move || {
+                let asset = "default".to_string();
+                loop {
+                    match transfer(&db, &asset, &"a".to_string(), &format!("b{}", i), 3, None, None) {
+                        Ok(()) => return true,
+                        Err(LedgerError::InsufficientBalance { .. }) => return false,
+                        Err(ref e) if e.is_retryable() => continue,
+                        Err(e) => panic!("unexpected error: {}", e),
+                    }
+                }
+            })
+        }).collect();
+        let sent = threads.into_iter().map(|t| t.join().unwrap()).filter(|&sent| sent).count();
+
+        assert_eq!(sent, 3);
+        assert_eq!(get_balance(&db, &asset, &"a".to_string()).unwrap(), 1);
+        let received: usize = (0..8).filter_map(|i| get_balance(&db, &asset, &format!("b{}", i)).ok()).sum();
+        assert_eq!(received, 9);
+    }
+
+    #[test]
+    fn transfer_of_a_locked_sender_is_a_retryable_conflict() {
+        let dir = TempDir::new("conflict").unwrap();
+        let db = setup(&dir);
+        let asset = "default".to_string();
+        let a = "a".to_string();
+        transfer(&db, &asset, &"S".to_string(), &a, 10, None, None).unwrap();
+
+        let held = lock_keys(&db, vec![account_key(&asset, &a)]).unwrap();
+        match transfer(&db, &asset, &a, &"b".to_string(), 1, None, None) {
+            Err(ref e @ LedgerError::Conflict(_)) => assert!(e.is_retryable()),
+            r => panic!("expected a conflict, got {:?}", r),
+        }
+        assert_eq!(get_balance(&db, &asset, &a).unwrap(), 10);
+
+        drop(held);
+        transfer(&db, &asset, &a, &"b".to_string(), 1, None, None).unwrap();
+        assert_eq!(get_balance(&db, &asset, &a).unwrap(), 9);
+    }
+
+    #[test]
+    fn batch_indexes_tokens_passed_along_legs_to_where_they_end_up() {
+        let dir = TempDir::new("batch_index").unwrap();
+        let db = setup(&dir);
+        let asset = "default".to_string();
+        let (s, a, b, c, d) = ("S".to_string(), "a".to_string(), "b".to_string(), "c".to_string(), "d".to_string());
+        transfer(&db, &asset, &s, &a, 10, None, None).unwrap();
+
+        //ids 6..10 go a -> b, 7..10 go on to c, 9 to d, and b sends its last id back to a.
+        transfer_batch(&db, &asset, &[(s.clone(), b.clone(), 2), (a.clone(), b.clone(), 4), (b.clone(), c.clone(), 3),
+                                     (c.clone(), d.clone(), 1), (b.clone(), a.clone(), 1)]).unwrap();
+
+        let expected: Vec<(&String, usize)> = vec![(&a, 7), (&b, 2), (&c, 2), (&d, 1)];
+        for (account, balance) in expected {
+            let tokens = load_account(&db, &asset, account).unwrap();
+            assert_eq!(tokens.len(), balance);
+            for &(start, end) in tokens.ranges() {
+                for id in start..end {
+                    assert_eq!(owner_index::lookup(&db, &asset, id).unwrap().as_ref(), Some(account), "token {}", id);
+                }
+            }
+        }
+        assert!(audit(&db, &asset).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+extern crate exonum_rocksdb;
+extern crate bincode;
+
+use std::result::Result;
+use self::exonum_rocksdb::WriteBatch;
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::{self, account_key, allowance_key};
+use super::lock::lock_keys;
+use super::auth;
+use super::freeze::check_not_frozen;
+use super::fee;
+use super::registry::check_registered;
+use super::account::{get_super_account_id, usize_bytes, load_account, take_amount,
+                     transfer_lock_keys, commit_end_user_transfer, TransferExtras};
+
+//delegated spending: owner approves an amount, spender moves up to that amount out of owner's account.
+
+//set how many tokens spender may take from owner, replacing any earlier approval. zero revokes it.
+pub fn approve(db: &Ledger, asset: &String, owner: &String, spender: &String, amount: usize) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
+
+    if owner.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty owner id"));
+    }
+
+    if spender.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty spender id"));
+    }
+
+    if owner == spender {
+        return Err(LedgerError::InvalidInput("owner cannot approve itself"));
+    }
+
+    //super account hands out tokens by rolling them out, not through allowances.
+    if get_super_account_id(db, asset)? == *owner {
+        return Err(LedgerError::InvalidInput("super account cannot approve a spender"));
+    }
+
+    //there is no signed variant of it yet, so an account with a public key cannot be approved for by the operator.
+    auth::check_unsigned(db, asset, owner)?;
+
+    //a frozen owner cannot hand its tokens out through a spender either, the flag is checked under the owner's lock.
+    let _locks = lock_keys(db, vec![account_key(asset, owner), allowance_key(asset, owner, spender)])?;
+    check_not_frozen(db, asset, owner)?;
+    let mut batch = WriteBatch::default();
+    put_allowance(&mut batch, asset, owner, spender, amount)?;
+    db.write(batch)?;
+
+    println!("'{}' is allowed to take {} tokens of '{}'.", spender, amount, owner);
+    Ok(())
+}
+
+//how many tokens spender may still take from owner.
+pub fn allowance(db: &Ledger, asset: &String, owner: &String, spender: &String) -> Result<usize, LedgerError> {
+    keys::check_asset(asset)?;
+
+    match db.get(&allowance_key(asset, owner, spender))? {
+        Some(bytes) => {
+            match bincode::serde::deserialize(&bytes) {
+                Ok(value) => Ok(value),
+                Err(_) => Err(LedgerError::Codec("allowance")),
+            }
+        },
+        None => Ok(0),
+    }
+}
+
+//spender moves amount tokens of owner to `to`, the allowance is lowered in the same batch as the balances.
+pub fn transfer_from(db: &Ledger, asset: &String, spender: &String, owner: &String, to: &String, amount: usize) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
+
+    if spender.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty spender id"));
+    }
+
+    if owner.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty owner id"));
+    }
+
+    if to.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty receiver id"));
+    }
+
+    if amount == 0 {
+        return Err(LedgerError::InvalidInput("amount to transfer must be greater than zero"));
+    }
+
+    if get_super_account_id(db, asset)? == *to {
+        return Err(LedgerError::InvalidInput("transfer to super account is not allowed"));
+    }
+
+    auth::check_unsigned(db, asset, spender)?;
+
+    let mut lock_list = transfer_lock_keys(db, asset, owner, to)?;
+    lock_list.push(account_key(asset, spender));
+    lock_list.push(allowance_key(asset, owner, spender));
+    let _locks = lock_keys(db, lock_list)?;
+    check_not_frozen(db, asset, spender)?;
+    check_not_frozen(db, asset, owner)?;
+    check_not_frozen(db, asset, to)?;
+    check_registered(db, asset, to)?;
+
+    let allowed = allowance(db, asset, owner, spender)?;
+    if allowed < amount {
+        return Err(LedgerError::InsufficientAllowance {
+            owner: owner.clone(),
+            spender: spender.clone(),
+            allowance: allowed,
+            requested: amount,
+        });
+    }
+
+    let mut owner_balance = load_account(db, asset, owner)?;
+    let tx_tokens = take_amount(&mut owner_balance, owner, amount)?;
+
+    println!("'{}' is transfering from '{}' to '{}' with {} tokens", spender, owner, to, amount);
+    //the fee is paid by the owner like on a transfer of its own, it does not use up the allowance.
+    let extras = TransferExtras {
+        tx_id: None,
+        nonce: None,
+        allowance: Some((spender, allowed - amount)),
+        fee: fee::take_fee(db, asset, owner, &mut owner_balance, amount)?,
+    };
+    commit_end_user_transfer(db, asset, owner, to, &owner_balance, &tx_tokens, &extras)
+}
+
+//a used up allowance is deleted instead of kept as zero.
+pub(crate) fn put_allowance(batch: &mut WriteBatch, asset: &String, owner: &String, spender: &String, amount: usize) -> Result<(), LedgerError> {
+    if amount == 0 {
+        batch.delete(&allowance_key(asset, owner, spender))?;
+    } else {
+        batch.put(&allowance_key(asset, owner, spender), &usize_bytes(amount))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,271 @@
+extern crate exonum_rocksdb;
+extern crate bincode;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::result::Result;
+use self::exonum_rocksdb::Snapshot;
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::{self, meta_key};
+use super::token_set::{TokenSet, balance_from_bytes};
+use super::escrow;
+
+//read only check of the ledger invariants of an asset, all reads go through one snapshot so concurrent
+//transfers cannot make a consistent ledger look broken:
+//  every account's header matches the number of tokens in its ranges, and its ranges are sorted and disjoint.
+//  end user balances + escrowed tokens + SuperBalance == TotalSupply.
+//  no token id is held twice, and nobody but super account holds an id that was not rolled out.
+//  the owner index points every held id to its holder and nothing else, escrowed ids to the sender.
+
+#[derive(Debug, Clone)]
+pub struct Violation {
+    //keys of the values breaking the invariant, printable.
+    pub keys: Vec<String>,
+    pub reason: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.keys.join(", "), self.reason)
+    }
+}
+
+//every violation found, empty if the ledger is consistent.
+pub fn audit(db: &Ledger, asset: &String) -> Result<Vec<Violation>, LedgerError> {
+    keys::check_asset(asset)?;
+
+    let snapshot = db.snapshot();
+    if snapshot.get(&meta_key(asset, keys::SUPER_ACCOUNT))?.is_none() {
+        return Err(LedgerError::NotInitialized);
+    }
+
+    let total_supply = match read_usize(&snapshot, &meta_key(asset, keys::TOTAL_SUPPLY))? {
+        Some(value) => value,
+        None => return Err(LedgerError::NotInitialized),
+    };
+    let super_balance = match read_usize(&snapshot, &meta_key(asset, keys::SUPER_BALANCE))? {
+        Some(value) => value,
+        None => return Err(LedgerError::NotInitialized),
+    };
+    let burned = read_usize(&snapshot, &meta_key(asset, keys::BURNED))?.unwrap_or(0);
+
+    let mut violations: Vec<Violation> = Vec::new();
+    //(start, end, key) of every range held by an account or an escrow.
+    let mut held: Vec<(usize, usize, String)> = Vec::new();
+    let mut balances: usize = 0;
+    let mut escrowed: usize = 0;
+    //tokens every account should be the owner of in the index.
+    let mut owned: BTreeMap<String, TokenSet> = BTreeMap::new();
+
+    let prefix = keys::asset_prefix(keys::ACCOUNT_PREFIX, asset);
+    let mut iter = snapshot.raw_iterator();
+    iter.seek(&prefix);
+    while iter.valid() {
+        let key = iter.key().unwrap();
+        if !key.starts_with(&prefix) {
+            break;
+        }
+
+        let name = String::from_utf8_lossy(&key).into_owned();
+        match check_account(&name, &iter.value().unwrap(), &mut violations) {
+            Some(tokens) => {
+                balances += tokens.len();
+                for &(start, end) in tokens.ranges() {
+                    held.push((start, end, name.clone()));
+                }
+                let account = String::from_utf8_lossy(&key[prefix.len()..]).into_owned();
+                owned.entry(account).or_insert_with(TokenSet::new).union(&tokens);
+            },
+            None => {},
+        }
+        iter.next();
+    }
+
+    let prefix = keys::asset_prefix(keys::ESCROW_PREFIX, asset);
+    let mut iter = snapshot.raw_iterator();
+    iter.seek(&prefix);
+    while iter.valid() {
+        let key = iter.key().unwrap();
+        if !key.starts_with(&prefix) {
+            break;
+        }
+
+        let id = keys::from_be_bytes(&key[prefix.len()..]);
+        let name = match id {
+            Some(id) => format!("e:{}:{}", asset, id),
+            None => String::from_utf8_lossy(&key).into_owned(),
+        };
+        match id.map(|id| escrow::decode_escrow(id, &iter.value().unwrap())) {
+            Some(Ok(escrow)) => {
+                escrowed += escrow.tokens.len();
+                for &(start, end) in escrow.tokens.ranges() {
+                    held.push((start, end, name.clone()));
+                }
+                owned.entry(escrow.from.clone()).or_insert_with(TokenSet::new).union(&escrow.tokens);
+            },
+            _ => violations.push(Violation { keys: vec![name], reason: String::from("cannot decode escrow") }),
+        }
+        iter.next();
+    }
+
+    if balances + escrowed + super_balance != total_supply {
+        violations.push(Violation {
+            keys: vec![meta_name(asset, keys::TOTAL_SUPPLY), meta_name(asset, keys::SUPER_BALANCE)],
+            reason: format!("balances {} + escrowed {} + super balance {} != total supply {}",
+                            balances, escrowed, super_balance, total_supply),
+        });
+    }
+
+    //ids below rolled_end left super account, the tail block [rolled_end, TotalSupply + Burned) is still its own.
+    let issued_end = total_supply + burned;
+    let rolled_end = if super_balance <= issued_end {
+        issued_end - super_balance
+    } else {
+        violations.push(Violation {
+            keys: vec![meta_name(asset, keys::SUPER_BALANCE)],
+            reason: format!("super balance {} is more than the {} ids ever issued", super_balance, issued_end),
+        });
+        0
+    };
+
+    held.sort();
+    let mut last: Option<(usize, String)> = None;
+    for &(start, end, ref name) in &held {
+        if end > rolled_end {
+            violations.push(Violation {
+                keys: vec![name.clone()],
+                reason: format!("holds ids [{}, {}) not rolled out yet, rolled out ids end at {}", start, end, rolled_end),
+            });
+        }
+
+        if let Some((last_end, ref last_name)) = last {
+            if start < last_end {
+                violations.push(Violation {
+                    keys: vec![last_name.clone(), name.clone()],
+                    reason: format!("both hold ids from {} to {}", start, if end < last_end { end } else { last_end }),
+                });
+            }
+        }
+
+        let further = match last {
+            Some((last_end, _)) => end > last_end,
+            None => true,
+        };
+        if further {
+            last = Some((end, name.clone()));
+        }
+    }
+
+    check_owner_index(&snapshot, asset, &owned, &mut violations)?;
+    Ok(violations)
+}
+
+//compare the owner index with the tokens every account holds, entries must not overlap either.
+fn check_owner_index(snapshot: &Snapshot, asset: &String, owned: &BTreeMap<String, TokenSet>, violations: &mut Vec<Violation>) -> Result<(), LedgerError> {
+    let index_name = String::from_utf8_lossy(&keys::asset_prefix(keys::OWNER_INDEX_PREFIX, asset)).into_owned();
+    let mut indexed: BTreeMap<String, TokenSet> = BTreeMap::new();
+    let mut last_end: usize = 0;
+
+    let prefix = keys::asset_prefix(keys::OWNER_INDEX_PREFIX, asset);
+    let mut iter = snapshot.raw_iterator();
+    iter.seek(&prefix);
+    while iter.valid() {
+        let key = iter.key().unwrap();
+        if !key.starts_with(&prefix) {
+            break;
+        }
+
+        let name = String::from_utf8_lossy(&key).into_owned();
+        let end = keys::from_be_bytes(&key[prefix.len()..]);
+        let entry: Option<(usize, String)> = bincode::serde::deserialize(&iter.value().unwrap()).ok();
+        match (end, entry) {
+            (Some(end), Some((start, owner))) if start < end => {
+                if start < last_end {
+                    violations.push(Violation {
+                        keys: vec![index_name.clone()],
+                        reason: format!("entry [{}, {}) of '{}' overlaps the entry before it", start, end, owner),
+                    });
+                }
+                last_end = end;
+                indexed.entry(owner).or_insert_with(TokenSet::new).insert_range(start, end);
+            },
+            _ => violations.push(Violation { keys: vec![name], reason: String::from("cannot decode owner index entry") }),
+        }
+        iter.next();
+    }
+
+    let empty = TokenSet::new();
+    let mut accounts: Vec<&String> = owned.keys().chain(indexed.keys()).collect();
+    accounts.sort();
+    accounts.dedup();
+    for account in accounts {
+        let holds = owned.get(account).unwrap_or(&empty);
+        let points = indexed.get(account).unwrap_or(&empty);
+        if holds != points {
+            violations.push(Violation {
+                keys: vec![index_name.clone()],
+                reason: format!("index points {} ids to '{}' in {:?}, it holds {} ids in {:?}",
+                                points.len(), account, points.ranges(), holds.len(), holds.ranges()),
+            });
+        }
+    }
+    Ok(())
+}
+
+//decode an account's token set, None if it cannot be decoded at all.
+fn check_account(name: &String, bytes: &[u8], violations: &mut Vec<Violation>) -> Option<TokenSet> {
+    let tokens = match TokenSet::from_bytes_unchecked(bytes) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            violations.push(Violation { keys: vec![name.clone()], reason: format!("{}", e) });
+            return None;
+        },
+    };
+
+    let mut count: usize = 0;
+    let mut last_end: Option<usize> = None;
+    for &(start, end) in tokens.ranges() {
+        if start >= end || last_end.map_or(false, |last_end| start < last_end) {
+            violations.push(Violation {
+                keys: vec![name.clone()],
+                reason: format!("range [{}, {}) is empty or out of order", start, end),
+            });
+        }
+        if end > start {
+            count += end - start;
+        }
+        last_end = Some(end);
+    }
+
+    //the header is what get_balance reports, it must agree with the ranges the merge handler wrote.
+    let header = balance_from_bytes(bytes).unwrap_or(0);
+    if header != count {
+        violations.push(Violation {
+            keys: vec![name.clone()],
+            reason: format!("header says {} tokens, ranges hold {}", header, count),
+        });
+    }
+
+    let mut rebuilt = TokenSet::new();
+    for &(start, end) in tokens.ranges() {
+        rebuilt.insert_range(start, end);
+    }
+    Some(rebuilt)
+}
+
+fn read_usize(snapshot: &Snapshot, key: &[u8]) -> Result<Option<usize>, LedgerError> {
+    match snapshot.get(key)? {
+        Some(bytes) => {
+            match bincode::serde::deserialize(&bytes) {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(LedgerError::Codec("ledger meta")),
+            }
+        },
+        None => Ok(None),
+    }
+}
+
+fn meta_name(asset: &String, name: &str) -> String {
+    String::from_utf8_lossy(&meta_key(asset, name)).into_owned()
+}
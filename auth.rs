@@ -0,0 +1,167 @@
+extern crate exonum_rocksdb;
+extern crate bincode;
+extern crate ed25519_dalek;
+
+use std::result::Result;
+use self::exonum_rocksdb::WriteBatch;
+use self::ed25519_dalek::{Signature, VerifyingKey, PUBLIC_KEY_LENGTH};
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::{self, public_key_key, nonce_key};
+use super::lock::lock_keys;
+use super::account::{usize_bytes, get_super_account_id};
+
+//an account with a registered ed25519 public key can only send tokens with a signed transfer,
+//accounts without one are still moved by the operator alone.
+
+//what a signed transfer carries besides from, to and amount.
+#[derive(Debug, Clone)]
+pub struct TransferAuth {
+    //must be greater than the last nonce the sender used, so a signed transfer cannot be replayed.
+    pub nonce: usize,
+    pub signature: Vec<u8>,
+}
+
+//register the ed25519 public key of an account by super account, a registered key cannot be replaced.
+//a key gives its owner exclusive control of the account, so nobody else may attach one.
+pub fn register_public_key(db: &Ledger, asset: &String, admin: &String, account: &String, public_key: &[u8]) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
+
+    if account.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty account id"));
+    }
+
+    parse_public_key(public_key)?;
+
+    if get_super_account_id(db, asset)? != *admin {
+        return Err(LedgerError::PermissionDenied(admin.clone()));
+    }
+    check_unsigned(db, asset, admin)?;
+
+    //mint, burn and registering keys carry no signature yet, a key on super account would lock all of them.
+    if *account == *admin {
+        return Err(LedgerError::InvalidInput("super account cannot register a public key until admin operations are signed"));
+    }
+
+    let _locks = lock_keys(db, vec![public_key_key(asset, account)])?;
+    if db.get(&public_key_key(asset, account))?.is_some() {
+        return Err(LedgerError::InvalidInput("account has a public key already"));
+    }
+
+    db.put(&public_key_key(asset, account), public_key)?;
+    println!("Public key of '{}' registered.", account);
+    Ok(())
+}
+
+pub fn public_key_of(db: &Ledger, asset: &String, account: &String) -> Result<Option<Vec<u8>>, LedgerError> {
+    match db.get(&public_key_key(asset, account))? {
+        Some(value) => Ok(Some(value.to_vec())),
+        None => Ok(None),
+    }
+}
+
+//bytes the sender signs. asset is part of it so a signature cannot be replayed on another token system.
+pub fn transfer_message(asset: &String, from: &String, to: &String, amount: usize, nonce: usize) -> Vec<u8> {
+    bincode::serde::serialize(&(asset, from, to, amount, nonce), bincode::SizeLimit::Infinite).unwrap()
+}
+
+//check the signature of a transfer if the sender has a public key, returns the nonce to save with the transfer.
+//caller must hold the lock of the sender's nonce.
+pub(crate) fn check_transfer(db: &Ledger, asset: &String, from: &String, to: &String, amount: usize, auth: Option<&TransferAuth>) -> Result<Option<usize>, LedgerError> {
+    let public_key = match public_key_of(db, asset, from)? {
+        Some(public_key) => public_key,
+        None => return Ok(None),
+    };
+
+    let auth = match auth {
+        Some(auth) => auth,
+        None => return Err(LedgerError::BadSignature(from.clone())),
+    };
+
+    if let Some(last) = last_nonce(db, asset, from)? {
+        if auth.nonce <= last {
+            return Err(LedgerError::NonceReplayed { account: from.clone(), nonce: auth.nonce, last: last });
+        }
+    }
+
+    let verifying_key = parse_public_key(&public_key)?;
+    let signature = match Signature::from_slice(&auth.signature) {
+        Ok(signature) => signature,
+        Err(_) => return Err(LedgerError::BadSignature(from.clone())),
+    };
+
+    let message = transfer_message(asset, from, to, amount, auth.nonce);
+    match verifying_key.verify_strict(&message, &signature) {
+        Ok(()) => Ok(Some(auth.nonce)),
+        Err(_) => Err(LedgerError::BadSignature(from.clone())),
+    }
+}
+
+//operations that carry no signature are refused on accounts that registered a public key.
+pub(crate) fn check_unsigned(db: &Ledger, asset: &String, account: &String) -> Result<(), LedgerError> {
+    match public_key_of(db, asset, account)? {
+        Some(_) => Err(LedgerError::PermissionDenied(account.clone())),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn put_nonce(batch: &mut WriteBatch, asset: &String, account: &String, nonce: usize) -> Result<(), LedgerError> {
+    batch.put(&nonce_key(asset, account), &usize_bytes(nonce))?;
+    Ok(())
+}
+
+fn last_nonce(db: &Ledger, asset: &String, account: &String) -> Result<Option<usize>, LedgerError> {
+    match db.get(&nonce_key(asset, account))? {
+        Some(bytes) => {
+            match bincode::serde::deserialize(&bytes) {
+                Ok(nonce) => Ok(Some(nonce)),
+                Err(_) => Err(LedgerError::Codec("nonce")),
+            }
+        },
+        None => Ok(None),
+    }
+}
+
+fn parse_public_key(bytes: &[u8]) -> Result<VerifyingKey, LedgerError> {
+    if bytes.len() != PUBLIC_KEY_LENGTH {
+        return Err(LedgerError::InvalidInput("public key must be 32 bytes"));
+    }
+
+    let mut key = [0u8; PUBLIC_KEY_LENGTH];
+    key.copy_from_slice(bytes);
+    match VerifyingKey::from_bytes(&key) {
+        Ok(key) => Ok(key),
+        Err(_) => Err(LedgerError::InvalidInput("not an ed25519 public key")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use self::tempdir::TempDir;
+    use super::ed25519_dalek::SigningKey;
+    use super::{register_public_key, public_key_of};
+    use super::super::ledger::open_ledger;
+    use super::super::config::DbConfig;
+    use super::super::error::LedgerError;
+    use super::super::bootstrap::init_super_account;
+
+    #[test]
+    fn super_account_cannot_register_its_own_key() {
+        let dir = TempDir::new("super_key").unwrap();
+        let db = open_ledger(dir.path().to_str().unwrap(), &DbConfig::default()).unwrap();
+        let (asset, super_id) = ("default".to_string(), "S".to_string());
+        init_super_account(&db, &asset, &super_id, 1000, None).unwrap();
+        let key = SigningKey::from_bytes(&[7u8; 32]).verifying_key();
+
+        match register_public_key(&db, &asset, &super_id, &super_id, key.as_bytes()) {
+            Err(LedgerError::InvalidInput(_)) => {},
+            r => panic!("{:?}", r),
+        }
+        assert!(public_key_of(&db, &asset, &super_id).unwrap().is_none());
+
+        register_public_key(&db, &asset, &super_id, &"a".to_string(), key.as_bytes()).unwrap();
+        assert!(public_key_of(&db, &asset, &"a".to_string()).unwrap().is_some());
+    }
+}
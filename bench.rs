@@ -1,98 +1,62 @@
-extern crate exonum_rocksdb;
-
 use std::time::Instant;
 use std::result::Result;
-use self::exonum_rocksdb::DB;
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::DEFAULT_ASSET;
 use super::bootstrap::init_super_account;
 use super::account::{get_balance, transfer};
+use super::family::reset_ledger;
 
 
-pub fn bench_test(db: &mut DB, io: &str, num_of_tokens: usize, num_of_accounts: usize) -> Result<(), &'static str> {
+pub fn bench_test(db: &Ledger, io: &str, num_of_tokens: usize, num_of_accounts: usize) -> Result<(), LedgerError> {
     if io.len() == 0 {
-        return Err("Invalid IO command!");
+        return Err(LedgerError::InvalidInput("empty IO command"));
     }
 
     if num_of_tokens == 0 {
-        return Err("Invalid amount of tokens");
+        return Err(LedgerError::InvalidInput("amount of tokens must be greater than zero"));
     }
 
     if num_of_accounts ==  0 {
-        return Err("Invalid amount of tokens");
+        return Err(LedgerError::InvalidInput("amount of accounts must be greater than zero"));
     }
 
     if num_of_tokens * num_of_accounts > 50000000000 {
         println!("{} * {} > 50,000,000,000!", num_of_tokens, num_of_accounts);
-        return Err("Your input: num_of_tokens * num_of_account exceed limitation: 50B tokens.");
+        return Err(LedgerError::InvalidInput("num_of_tokens * num_of_account exceed limitation: 50B tokens"));
     }
 
     match io {
-        "I" => {
-            match bench_test_write(db, num_of_tokens, num_of_accounts) {
-	            Ok(()) => println!("Bench test done!"),
-                Err(e) => {
-                    println!("Got error: {:?}", e);
-                    return Err("Bench test write failed.");   
-                }
-            }
-        }
-        "O" => {
-            match bench_test_read(db, num_of_tokens, num_of_accounts) {
-                Ok(()) => println!("Bench test done!"),
-                Err(e) => {
-                    println!("Got error: {:?}", e);
-                    return Err("Bench test read failed.");   
-                }
-            }
-        }
-        "IO"=> {
-            match bench_test_rw(db, num_of_tokens, num_of_accounts) {
-                Ok(()) => println!("Bench test done!"),
-                Err(e) => {
-                    println!("Got error: {:?}", e);
-                    return Err("Bench test rw failed.");   
-                }
-            }
-        }
+        "I" => bench_test_write(db, num_of_tokens, num_of_accounts)?,
+        "O" => bench_test_read(db, num_of_tokens, num_of_accounts)?,
+        "IO"=> bench_test_rw(db, num_of_tokens, num_of_accounts)?,
         _ => {
-	        return Err("Invalid IO command");
+	        return Err(LedgerError::InvalidInput("IO command must be one of I | O | IO"));
         }
     }
 
+    println!("Bench test done!");
     Ok(())
 }
 
-fn bench_test_write(db: &mut DB, num_of_tokens: usize, num_of_accounts: usize) -> Result<(), &'static str> {
+fn bench_test_write(db: &Ledger, num_of_tokens: usize, num_of_accounts: usize) -> Result<(), LedgerError> {
     // before write test, drop the old data.
-    match db.drop_cf("default") {
-        Ok(()) => println!("Old data droped."),
-        Err(_e) => println!("default cf does not existed, skip to drop cf."),
-    }
+    let deleted = reset_ledger(db)?;
+    println!("Old data droped, {} keys.", deleted);
 
-    match init_super_account(db, &String::from("SUPER"), 50_000_000_000){
-        Ok(()) => println!("Token id system is ready to use."),
-        Err(e) => {
-            println!("Got error: {:?}", e);
-            return Err("Cannot init super account.");
-        },
-    }
+    init_super_account(db, &String::from(DEFAULT_ASSET), &String::from("SUPER"), 50_000_000_000, None)?;
+    println!("Token id system is ready to use.");
 
     let start = Instant::now();
     let mut last_time_ms: u64 = 0;
 
     for id in 0 .. num_of_accounts {
-        // hard code super account only for bench test.
-        match transfer(db, &String::from("SUPER"), &format!("{}", id), num_of_tokens) {
-            Ok(()) => {
-                let elapsed = start.elapsed();
-                let now_ms = (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64;
-                println!("Last write cost: {} ms", now_ms - last_time_ms);
-                last_time_ms = now_ms;
-            }
-            Err(e) => {
-                println!("Got error {:?}", e);
-                return Err("Transfer failed during bench test.");
-            }
-        }
+        // hard code super account and the default asset only for bench test.
+        transfer(db, &String::from(DEFAULT_ASSET), &String::from("SUPER"), &format!("{}", id), num_of_tokens, None, None)?;
+        let elapsed = start.elapsed();
+        let now_ms = (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64;
+        println!("Last write cost: {} ms", now_ms - last_time_ms);
+        last_time_ms = now_ms;
     }
 
     let total_elapsed = start.elapsed();
@@ -102,41 +66,33 @@ fn bench_test_write(db: &mut DB, num_of_tokens: usize, num_of_accounts: usize) -
     Ok(())
 }
 
-fn bench_test_read(db: &DB, num_of_tokens: usize, num_of_accounts: usize) -> Result<(), &'static str> {
+fn bench_test_read(db: &Ledger, num_of_tokens: usize, num_of_accounts: usize) -> Result<(), LedgerError> {
     if num_of_tokens == 0 {
-        return Err("Invalid num of tokens.");
+        return Err(LedgerError::InvalidInput("amount of tokens must be greater than zero"));
     }
 	
     if num_of_accounts == 0 {
-        return Err("Invalid num of accounts.");
+        return Err(LedgerError::InvalidInput("amount of accounts must be greater than zero"));
     }
 	
     // before read test, check if the db have enough account for reading.
     let max_account_id = num_of_accounts - 1;
-    match get_balance(db, &format!("{}", max_account_id)) {
+    match get_balance(db, &String::from(DEFAULT_ASSET), &format!("{}", max_account_id)) {
         Ok(value) => println!("Account ID: {} have {} tokens.", max_account_id, value),
         Err(e) => {
-            println!("Got error: {:?}", e);
             println!("You might need start bench writing test to prepare the data before reading test.");
-            return Err("You might need start bench writing test to prepare the data before reading test");
+            return Err(e);
         }
     }
 
     let start = Instant::now();
     let mut last_time_ms: u64 = 0;
     for id in 0 .. num_of_accounts {
-        match get_balance(db, &format!("{}", id)) {
-            Ok(_value) => {
-                let elapsed = start.elapsed();
-                let now_ms = (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64;
-                println!("Last read cost: {} ms", now_ms - last_time_ms);
-                last_time_ms = now_ms;
-            }
-            Err(e) => {
-                println!("Got error {:?}", e);
-                return Err("Get balance failed during bench test.");
-            }
-        }
+        get_balance(db, &String::from(DEFAULT_ASSET), &format!("{}", id))?;
+        let elapsed = start.elapsed();
+        let now_ms = (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64;
+        println!("Last read cost: {} ms", now_ms - last_time_ms);
+        last_time_ms = now_ms;
     }
 
     let total_elapsed = start.elapsed();
@@ -146,51 +102,30 @@ fn bench_test_read(db: &DB, num_of_tokens: usize, num_of_accounts: usize) -> Res
     Ok(())
 }
 
-fn bench_test_rw(db: &mut DB, num_of_tokens: usize, num_of_accounts: usize) -> Result<(), &'static str> {
+fn bench_test_rw(db: &Ledger, num_of_tokens: usize, num_of_accounts: usize) -> Result<(), LedgerError> {
     // before write test, drop the old data.
-    match db.drop_cf("default") {
-        Ok(()) => println!("Old data droped."),
-        Err(_e) => println!("default cf does not existed, skip to drop cf."),
-    }
+    let deleted = reset_ledger(db)?;
+    println!("Old data droped, {} keys.", deleted);
 
-    match init_super_account(db, &String::from("SUPER"), 50000000000){
-        Ok(()) => println!("Token id system is ready to use."),
-        Err(e) => {
-            println!("Got error: {:?}", e);
-            return Err("Cannot init super account.");
-        },
-    }
+    init_super_account(db, &String::from(DEFAULT_ASSET), &String::from("SUPER"), 50000000000, None)?;
+    println!("Token id system is ready to use.");
 
     let start = Instant::now();
     let mut last_time_ms: u64 = 0;
     for id in 0 .. num_of_accounts {
-        // hard code super account only for bench test.
-        match transfer(db, &String::from("SUPER"), &format!("{}", id), num_of_tokens) {
-            Ok(()) => {
-                let elapsed = start.elapsed();
-                let now_ms = (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64;
-                println!("Last write cost: {} ms", now_ms - last_time_ms);
-                last_time_ms = now_ms;
-            }
-            Err(e) => {
-                println!("Got error {:?}", e);
-                return Err("Transfer failed happens during bench test.");
-            }
-        }
+        // hard code super account and the default asset only for bench test.
+        transfer(db, &String::from(DEFAULT_ASSET), &String::from("SUPER"), &format!("{}", id), num_of_tokens, None, None)?;
+        let elapsed = start.elapsed();
+        let now_ms = (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64;
+        println!("Last write cost: {} ms", now_ms - last_time_ms);
+        last_time_ms = now_ms;
 
         // read balance here might be faster since the buffer hit happens usually right after the data insertion.
-        match get_balance(db, &format!("{}", id)) {
-            Ok(_value) => {
-                let elapsed = start.elapsed();
-                let now_ms = (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64;
-                println!("After write operation, immediately read cost: {} ms", now_ms - last_time_ms);
-                last_time_ms = now_ms;
-            }
-            Err(e) => {
-                println!("Got error {:?}", e);
-                return Err("Get balance failed during bench test.");
-            }
-        }
+        get_balance(db, &String::from(DEFAULT_ASSET), &format!("{}", id))?;
+        let elapsed = start.elapsed();
+        let now_ms = (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64;
+        println!("After write operation, immediately read cost: {} ms", now_ms - last_time_ms);
+        last_time_ms = now_ms;
     }
 
     let total_elapsed = start.elapsed();
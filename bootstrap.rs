@@ -2,36 +2,315 @@ extern crate exonum_rocksdb;
 extern crate bincode;
 
 use std::result::Result;
-use self::exonum_rocksdb::{DB, WriteBatch};
+use self::exonum_rocksdb::{WriteBatch, IteratorMode};
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::{self, account_key, meta_key, ledger_meta_key};
+use super::lock::lock_keys;
+use super::token_set::TokenSet;
+use super::owner_index;
+use super::account::{usize_bytes, get_super_account_id, get_super_account_value, get_burned, load_account};
+use super::fee::{self, FeeSchedule};
+use super::freeze::is_frozen;
+use super::escrow::pending_escrows;
+use super::journal::{self, JournalKind};
+use super::family::family_of;
+use super::auth;
+
+//fees, if given, are charged on every end user transfer of the asset.
+//an asset is initialized once, use rotate_super_account to hand it to another super account.
+pub fn init_super_account(db: &Ledger, asset: &String, super_id: &String, total_supply: usize, fees: Option<&FeeSchedule>) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
 
-//to do use result as return code sets for better code.
-pub fn init_super_account(db: &DB, super_id: &String, total_supply: usize) -> Result<(), &'static str> {
     if super_id.len() == 0 {
-        return Err("Invalid account id!");
+        return Err(LedgerError::InvalidInput("empty super account id"));
     }
 
     if total_supply == 0 {
-        return Err("Invalid total supply!");
+        return Err(LedgerError::InvalidInput("total supply must be greater than zero"));
+    }
+
+    if let Some(fees) = fees {
+        if fees.collector.len() == 0 {
+            return Err(LedgerError::InvalidInput("empty fee collector id"));
+        }
+
+        if fees.collector == *super_id {
+            return Err(LedgerError::InvalidInput("super account cannot collect fees"));
+        }
     }
 
-    println!("Going to init token id system with super account '{}' and totall supply:{}", super_id, total_supply);
+    println!("Going to init token id system '{}' with super account '{}' and totall supply:{}", asset, super_id, total_supply);
+
+    let _locks = lock_keys(db, vec![meta_key(asset, keys::SUPER_BALANCE)])?;
+    if db.get(&meta_key(asset, keys::SUPER_ACCOUNT))?.is_some() {
+        return Err(LedgerError::AlreadyInitialized);
+    }
+
+    //init writes the current schema version, which would turn migrate into a no-op and strand the old keys.
+    if has_legacy_keys(db)? {
+        return Err(LedgerError::InvalidInput("the token storage uses an older key layout, run migrate first"));
+    }
 
     let mut batch = WriteBatch::default();
-    let _ = batch.put(b"SuperAccount", &super_id.as_bytes());
+    batch.put(&meta_key(asset, keys::SUPER_ACCOUNT), &super_id.as_bytes())?;
     let totals = bincode::serde::serialize(&total_supply, bincode::SizeLimit::Infinite).unwrap();
-    let _ = batch.put(b"TotalSupply", &totals);
-    let _ = batch.put(b"SuperBalance", &totals);
+    batch.put(&meta_key(asset, keys::TOTAL_SUPPLY), &totals)?;
+    batch.put(&meta_key(asset, keys::SUPER_BALANCE), &totals)?;
+    batch.put(&meta_key(asset, keys::BURNED), &usize_bytes(0))?;
+    batch.put(&ledger_meta_key(keys::SCHEMA_VERSION), &schema_version_bytes())?;
+    if let Some(fees) = fees {
+        fee::put_fee_schedule(&mut batch, asset, fees)?;
+    }
     //atomic commit batch.
+    db.write(batch)?;
+
+    println!("You have super account '{}' initailized with total supply: {}.", super_id, total_supply);
+    Ok(())
+}
+
+//move super account authority from current to new_id. super account's tokens are the tail block of the issued ids,
+//counted by SuperBalance rather than kept under its account key, so they move with the id in the same write.
+//new_id must be a fresh account: no tokens, no pending escrow, not frozen and not the fee collector.
+pub fn rotate_super_account(db: &Ledger, asset: &String, current: &String, new_id: &String) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
+
+    if new_id.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty super account id"));
+    }
+
+    if current == new_id {
+        return Err(LedgerError::InvalidInput("new super account must differ from the current one"));
+    }
 
-    match db.write(batch) {
-        Ok(_) => {
-            println!("You have super account '{}' initailized with total supply: {}.", super_id, total_supply);
+    //roll outs, mints and burns of super account hold the super balance lock while they read and write.
+    let _locks = lock_keys(db, vec![meta_key(asset, keys::SUPER_BALANCE), account_key(asset, new_id)])?;
+    if get_super_account_id(db, asset)? != *current {
+        return Err(LedgerError::PermissionDenied(current.clone()));
+    }
+    //rotating carries no signature, a super account with a public key cannot be handed over by the operator.
+    auth::check_unsigned(db, asset, current)?;
+
+    match load_account(db, asset, new_id) {
+        Ok(ref balance) if balance.len() > 0 => {
+            return Err(LedgerError::InvalidInput("new super account must not hold any tokens"));
         },
-        Err(e) => {
-            println!("operational problem encountered: {}", e);
-            return Err("operational problem encountered!");
+        Ok(_) | Err(LedgerError::AccountNotFound(_)) => {},
+        Err(e) => return Err(e),
+    }
+
+    if is_frozen(db, asset, new_id)? {
+        return Err(LedgerError::AccountFrozen(new_id.clone()));
+    }
+
+    if let Some(fees) = fee::fee_schedule(db, asset)? {
+        if fees.collector == *new_id {
+            return Err(LedgerError::InvalidInput("super account cannot collect fees"));
         }
     }
 
+    if pending_escrows(db, asset)?.iter().any(|escrow| escrow.from == *new_id || escrow.to == *new_id) {
+        return Err(LedgerError::InvalidInput("new super account must not take part in a pending escrow"));
+    }
+
+    let super_balance = get_super_account_value(db, asset, keys::SUPER_BALANCE)?;
+    let issued_end = get_super_account_value(db, asset, keys::TOTAL_SUPPLY)? + get_burned(db, asset)?;
+    let mut tokens = TokenSet::new();
+    if super_balance > 0 {
+        tokens.insert_range(issued_end - super_balance, issued_end);
+    }
+
+    //atomic commit: the new id and the journal record of the handover.
+    let mut batch = WriteBatch::default();
+    batch.put(&meta_key(asset, keys::SUPER_ACCOUNT), &new_id.as_bytes())?;
+    let _seq_lock = journal::append(db, &mut batch, JournalKind::Rotation, asset, current, new_id, &tokens, None)?;
+    db.write(batch)?;
+
+    println!("Super account of '{}' is rotated from '{}' to '{}' with balance: {}.", asset, current, new_id, super_balance);
     Ok(())
 }
+
+//one-shot migration of a token_storage written with an older key layout, in one atomic batch:
+//  no schema version: un-prefixed keys, meta keys go under "m:default:" and every other key under "a:default:".
+//  schema version 1: namespaced keys of a single token system, they are moved into the default asset.
+//the owner index is rebuilt from the migrated balances, neither layout indexed every token: un-prefixed ledgers had
+//no index and v1 ledgers only indexed tokens moved after the index was added.
+//returns the number of keys moved. running it on an up to date db does nothing.
+pub fn migrate_legacy_keys(db: &Ledger) -> Result<usize, LedgerError> {
+    let version: Option<usize> = match db.get(&ledger_meta_key(keys::SCHEMA_VERSION))? {
+        Some(bytes) => {
+            match bincode::serde::deserialize(&bytes) {
+                Ok(version) => Some(version),
+                Err(_) => return Err(LedgerError::Codec("schema version")),
+            }
+        },
+        None => None,
+    };
+
+    if version == Some(keys::CURRENT_SCHEMA_VERSION) {
+        return Ok(0);
+    }
+
+    let mut batch = WriteBatch::default();
+    let mut moved: usize = 0;
+
+    let asset = keys::DEFAULT_ASSET.to_string();
+    let account_prefix = keys::asset_prefix(keys::ACCOUNT_PREFIX, keys::DEFAULT_ASSET);
+    for (key, value) in db.iterator(IteratorMode::Start) {
+        let (new_key, new_value) = match version {
+            None => unprefixed_to_asset_key(&key, &value)?,
+            Some(_) => {
+                if key[..] == ledger_meta_key(keys::SCHEMA_VERSION)[..] {
+                    continue;
+                }
+                if key.starts_with(keys::OWNER_INDEX_PREFIX) {
+                    batch.delete(&key)?;
+                    continue;
+                }
+                namespaced_to_asset_key(&key, &value)?
+            },
+        };
+
+        if new_key.starts_with(&account_prefix) {
+            let account = String::from_utf8_lossy(&new_key[account_prefix.len()..]).into_owned();
+            owner_index::index_owned(&mut batch, &asset, &TokenSet::from_bytes(&new_value)?, &account)?;
+        }
+        batch.put(&new_key, &new_value)?;
+        batch.delete(&key)?;
+        moved += 1;
+    }
+
+    batch.put(&ledger_meta_key(keys::SCHEMA_VERSION), &schema_version_bytes())?;
+    db.write(batch)?;
+
+    println!("Migrated {} keys to the asset scoped key layout.", moved);
+    Ok(moved)
+}
+
+//un-prefixed accounts hold the token ids as a bincode Vec<usize>, they are re-encoded as token sets.
+fn unprefixed_to_asset_key(key: &[u8], value: &[u8]) -> Result<(Vec<u8>, Vec<u8>), LedgerError> {
+    match keys::LEGACY_META_KEYS.iter().find(|name| name.as_bytes() == key) {
+        Some(name) => Ok((meta_key(keys::DEFAULT_ASSET, name), value.to_vec())),
+        None => {
+            let ids: Vec<usize> = match bincode::serde::deserialize(value) {
+                Ok(ids) => ids,
+                Err(_) => return Err(LedgerError::Codec("legacy account balance")),
+            };
+            let new_key = keys::prefixed(&keys::asset_prefix(keys::ACCOUNT_PREFIX, keys::DEFAULT_ASSET), key);
+            Ok((new_key, TokenSet::from_ids(&ids)?.to_bytes()))
+        },
+    }
+}
+
+//m:<name>, a:<account> and o:<end> keep their namespace and get the default asset inserted after it.
+fn namespaced_to_asset_key(key: &[u8], value: &[u8]) -> Result<(Vec<u8>, Vec<u8>), LedgerError> {
+    if key.len() < 2 {
+        return unprefixed_to_asset_key(key, value);
+    }
+
+    let namespace = &key[..2];
+    Ok((keys::prefixed(&keys::asset_prefix(namespace, keys::DEFAULT_ASSET), &key[2..]), value.to_vec()))
+}
+
+//true if the db holds keys of a layout older than the current one: a schema version below the current one,
+//or no schema version and a key outside of the ledger's namespaces.
+fn has_legacy_keys(db: &Ledger) -> Result<bool, LedgerError> {
+    match db.get(&ledger_meta_key(keys::SCHEMA_VERSION))? {
+        Some(bytes) => {
+            match bincode::serde::deserialize::<usize>(&bytes) {
+                Ok(version) => Ok(version < keys::CURRENT_SCHEMA_VERSION),
+                Err(_) => Err(LedgerError::Codec("schema version")),
+            }
+        },
+        None => {
+            for (key, _) in db.iterator(IteratorMode::Start) {
+                if family_of(&key).is_none() {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        },
+    }
+}
+
+fn schema_version_bytes() -> Vec<u8> {
+    bincode::serde::serialize(&keys::CURRENT_SCHEMA_VERSION, bincode::SizeLimit::Infinite).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+    extern crate bincode;
+
+    use self::tempdir::TempDir;
+    use super::{init_super_account, migrate_legacy_keys};
+    use super::super::ledger::{open_ledger, Ledger};
+    use super::super::config::DbConfig;
+    use super::super::token_set::TokenSet;
+    use super::super::keys;
+    use super::super::account::{get_balance, transfer, owner_of};
+    use super::super::audit::audit;
+
+    fn usize_value(value: usize) -> Vec<u8> {
+        bincode::serde::serialize(&value, bincode::SizeLimit::Infinite).unwrap()
+    }
+
+    fn open(dir: &TempDir) -> Ledger {
+        open_ledger(dir.path().to_str().unwrap(), &DbConfig::default()).unwrap()
+    }
+
+    //super account holds the tail [5, 100), b was rolled out the first 5 ids.
+    fn check_migrated(db: &Ledger) {
+        let asset = "default".to_string();
+        let b = "b".to_string();
+        let c = "c".to_string();
+        assert_eq!(get_balance(db, &asset, &"S".to_string()).unwrap(), 95);
+        assert_eq!(get_balance(db, &asset, &b).unwrap(), 5);
+        //tokens held from before the migration are found in the index.
+        assert_eq!(owner_of(db, &asset, 0).unwrap(), Some(b.clone()));
+        assert_eq!(owner_of(db, &asset, 4).unwrap(), Some(b.clone()));
+        assert_eq!(owner_of(db, &asset, 5).unwrap(), Some("S".to_string()));
+        assert_eq!(audit(db, &asset).unwrap().len(), 0);
+        transfer(db, &asset, &b, &c, 2, None, None).unwrap();
+        assert_eq!(get_balance(db, &asset, &b).unwrap(), 3);
+        assert_eq!(get_balance(db, &asset, &c).unwrap(), 2);
+        assert_eq!(owner_of(db, &asset, 4).unwrap(), Some(c.clone()));
+        assert_eq!(audit(db, &asset).unwrap().len(), 0);
+        assert_eq!(migrate_legacy_keys(db).unwrap(), 0);
+    }
+
+    #[test]
+    fn migrate_unprefixed_keys() {
+        let dir = TempDir::new("migrate_unprefixed").unwrap();
+        let db = open(&dir);
+        db.put(b"SuperAccount", b"S").unwrap();
+        db.put(b"TotalSupply", &usize_value(100)).unwrap();
+        db.put(b"SuperBalance", &usize_value(95)).unwrap();
+        let ids: Vec<usize> = vec![0, 1, 2, 3, 4];
+        db.put(b"b", &bincode::serde::serialize(&ids, bincode::SizeLimit::Infinite).unwrap()).unwrap();
+
+        assert!(init_super_account(&db, &"other".to_string(), &"S".to_string(), 100, None).is_err());
+        assert_eq!(migrate_legacy_keys(&db).unwrap(), 4);
+        assert!(db.get(b"b").unwrap().is_none());
+        check_migrated(&db);
+    }
+
+    #[test]
+    fn migrate_schema_v1_keys() {
+        let dir = TempDir::new("migrate_v1").unwrap();
+        let db = open(&dir);
+        db.put(b"m:SchemaVersion", &usize_value(1)).unwrap();
+        db.put(b"m:SuperAccount", b"S").unwrap();
+        db.put(b"m:TotalSupply", &usize_value(100)).unwrap();
+        db.put(b"m:SuperBalance", &usize_value(95)).unwrap();
+        db.put(b"a:b", &TokenSet::from_range(0, 5).to_bytes()).unwrap();
+        //a v1 ledger only indexed the tokens moved after the index was added, here id 4 of b.
+        let entry = bincode::serde::serialize(&(4usize, "b"), bincode::SizeLimit::Infinite).unwrap();
+        db.put(&[&b"o:"[..], &keys::be_bytes(5)[..]].concat(), &entry).unwrap();
+
+        assert!(init_super_account(&db, &"other".to_string(), &"S".to_string(), 100, None).is_err());
+        assert_eq!(migrate_legacy_keys(&db).unwrap(), 4);
+        assert!(db.get(b"a:b").unwrap().is_none());
+        check_migrated(&db);
+    }
+}
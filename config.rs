@@ -0,0 +1,309 @@
+extern crate exonum_rocksdb;
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::result::Result;
+use self::exonum_rocksdb::{Options, WriteOptions, BlockBasedOptions, DBCompressionType, DBCompactionStyle};
+use super::error::LedgerError;
+
+//rocksdb options of a token storage, so a deployment can be tuned without recompiling.
+//they are read from a config file of `name = value` lines, see token_poc.conf. it looks like toml but is not:
+//there are no tables, numbers and booleans are bare, the names of a compression or compaction style are quoted.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbConfig {
+    pub parallelism: i32,
+    pub max_background_flushes: i32,
+    pub max_background_compactions: i32,
+    //size of a memtable, rocksdb default is 64MB.
+    pub write_buffer_size: usize,
+    pub max_write_buffer_number: i32,
+    pub bytes_per_sync: u64,
+    pub block_size: usize,
+    //lru block cache shared by the tables, rocksdb default is 8MB.
+    pub block_cache_size: usize,
+    //bits per key of the bloom filter of a table, 0 turns it off.
+    pub bloom_filter_bits: i32,
+    pub compression: DBCompressionType,
+    pub compaction_style: DBCompactionStyle,
+    pub disable_auto_compactions: bool,
+    //-1 keeps every table file open.
+    pub max_open_files: i32,
+    //fsync instead of fdatasync the wal and the table files.
+    pub use_fsync: bool,
+    //sync the wal on every write, so a write survives a machine crash and not only a process crash.
+    pub sync_writes: bool,
+    //skip the wal, writes not flushed to a table yet are lost on a crash.
+    pub disable_wal: bool,
+}
+
+impl Default for DbConfig {
+    //the options the storage was tuned with before they were configurable.
+    fn default() -> DbConfig {
+        DbConfig {
+            parallelism: 8,
+            max_background_flushes: 6,
+            max_background_compactions: 6,
+            write_buffer_size: 128 * 1024 * 1024,
+            max_write_buffer_number: 6,
+            bytes_per_sync: 2 * 1024 * 1024,
+            block_size: 4096,
+            block_cache_size: 8 * 1024 * 1024,
+            bloom_filter_bits: 0,
+            compression: DBCompressionType::Snappy,
+            compaction_style: DBCompactionStyle::Level,
+            disable_auto_compactions: false,
+            max_open_files: -1,
+            use_fsync: false,
+            sync_writes: false,
+            disable_wal: false,
+        }
+    }
+}
+
+impl DbConfig {
+    //defaults overridden by every option of the file.
+    pub fn load(path: &str) -> Result<DbConfig, LedgerError> {
+        let mut text = String::new();
+        match File::open(path).and_then(|mut file| file.read_to_string(&mut text)) {
+            Ok(_) => {},
+            Err(e) => return Err(LedgerError::Config(format!("cannot read '{}': {}", path, e))),
+        }
+
+        let mut config = DbConfig::default();
+        for (number, line) in text.lines().enumerate() {
+            let at = |reason: String| LedgerError::Config(format!("{}:{}: {}", path, number + 1, reason));
+            //a '#' inside a quoted value is not a comment.
+            let line = match line.find('#') {
+                Some(index) if line[..index].matches('"').count() % 2 == 0 => &line[..index],
+                _ => line,
+            };
+            let line = line.trim();
+            if line.len() == 0 {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                return Err(at(format!("tables like {} are not supported, every option is a top level `name = value` line", line)));
+            }
+
+            let (name, value) = match line.find('=') {
+                Some(index) => (line[..index].trim(), line[index + 1..].trim()),
+                None => return Err(at(String::from("expected `name = value`"))),
+            };
+            let value = match unquote(name, value) {
+                Ok(value) => value,
+                Err(reason) => return Err(at(reason)),
+            };
+            match config.set(name, value) {
+                Ok(()) => {},
+                Err(LedgerError::Config(reason)) => return Err(at(reason)),
+                Err(e) => return Err(e),
+            }
+        }
+        config.validate().map_err(|e| match e {
+            LedgerError::Config(reason) => LedgerError::Config(format!("{}: {}", path, reason)),
+            e => e,
+        })?;
+        Ok(config)
+    }
+
+    //options that are fine one by one but cannot be combined.
+    pub fn validate(&self) -> Result<(), LedgerError> {
+        //rocksdb refuses a synced write without the wal.
+        if self.sync_writes && self.disable_wal {
+            return Err(LedgerError::Config("sync_writes cannot be set together with disable_wal".to_string()));
+        }
+        Ok(())
+    }
+
+    //set one option from its bare text form, used for the lines of a config file once unquoted
+    //and the command line overrides.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), LedgerError> {
+        match name {
+            "parallelism" => self.parallelism = parse_number(name, value)?,
+            "max_background_flushes" => self.max_background_flushes = parse_number(name, value)?,
+            "max_background_compactions" => self.max_background_compactions = parse_number(name, value)?,
+            "write_buffer_size" => self.write_buffer_size = parse_number(name, value)?,
+            "max_write_buffer_number" => self.max_write_buffer_number = parse_number(name, value)?,
+            "bytes_per_sync" => self.bytes_per_sync = parse_number(name, value)?,
+            "block_size" => self.block_size = parse_number(name, value)?,
+            "block_cache_size" => self.block_cache_size = parse_number(name, value)?,
+            "bloom_filter_bits" => self.bloom_filter_bits = parse_number(name, value)?,
+            "max_open_files" => self.max_open_files = parse_number(name, value)?,
+            "disable_auto_compactions" => self.disable_auto_compactions = parse_bool(name, value)?,
+            "use_fsync" => self.use_fsync = parse_bool(name, value)?,
+            "sync_writes" => self.sync_writes = parse_bool(name, value)?,
+            "disable_wal" => self.disable_wal = parse_bool(name, value)?,
+            "compression" => {
+                self.compression = match value {
+                    "none" => DBCompressionType::None,
+                    "snappy" => DBCompressionType::Snappy,
+                    "zlib" => DBCompressionType::Zlib,
+                    "bz2" => DBCompressionType::Bz2,
+                    "lz4" => DBCompressionType::Lz4,
+                    "lz4hc" => DBCompressionType::Lz4hc,
+                    _ => return Err(LedgerError::Config(format!("compression must be none, snappy, zlib, bz2, lz4 or lz4hc, got '{}'", value))),
+                }
+            },
+            "compaction_style" => {
+                self.compaction_style = match value {
+                    "level" => DBCompactionStyle::Level,
+                    "universal" => DBCompactionStyle::Universal,
+                    "fifo" => DBCompactionStyle::Fifo,
+                    _ => return Err(LedgerError::Config(format!("compaction_style must be level, universal or fifo, got '{}'", value))),
+                }
+            },
+            _ => return Err(LedgerError::Config(format!("unknown option '{}'", name))),
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, opts: &mut Options) {
+        let mut block_opts = BlockBasedOptions::default();
+        block_opts.set_block_size(self.block_size);
+        block_opts.set_lru_cache(self.block_cache_size);
+        if self.bloom_filter_bits > 0 {
+            block_opts.set_bloom_filter(self.bloom_filter_bits, false);
+        }
+        opts.set_block_based_table_factory(&block_opts);
+
+        opts.increase_parallelism(self.parallelism);
+        opts.set_max_background_flushes(self.max_background_flushes);
+        opts.set_max_background_compactions(self.max_background_compactions);
+        opts.set_write_buffer_size(self.write_buffer_size);
+        opts.set_max_write_buffer_number(self.max_write_buffer_number);
+        opts.set_bytes_per_sync(self.bytes_per_sync);
+        opts.set_compression_type(self.compression);
+        opts.set_compaction_style(self.compaction_style);
+        opts.set_disable_auto_compactions(self.disable_auto_compactions);
+        opts.set_max_open_files(self.max_open_files);
+        opts.set_use_fsync(self.use_fsync);
+    }
+
+    //the wal settings are per write in rocksdb, every write of the ledger uses these.
+    pub fn write_options(&self) -> WriteOptions {
+        let mut write_opts = WriteOptions::default();
+        write_opts.set_sync(self.sync_writes);
+        write_opts.disable_wal(self.disable_wal);
+        write_opts
+    }
+}
+
+//the same `name = value` lines a config file is made of.
+impl fmt::Display for DbConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let compression = match self.compression {
+            DBCompressionType::None => "none",
+            DBCompressionType::Snappy => "snappy",
+            DBCompressionType::Zlib => "zlib",
+            DBCompressionType::Bz2 => "bz2",
+            DBCompressionType::Lz4 => "lz4",
+            DBCompressionType::Lz4hc => "lz4hc",
+        };
+        let compaction_style = match self.compaction_style {
+            DBCompactionStyle::Level => "level",
+            DBCompactionStyle::Universal => "universal",
+            DBCompactionStyle::Fifo => "fifo",
+        };
+
+        writeln!(f, "parallelism = {}", self.parallelism)?;
+        writeln!(f, "max_background_flushes = {}", self.max_background_flushes)?;
+        writeln!(f, "max_background_compactions = {}", self.max_background_compactions)?;
+        writeln!(f, "write_buffer_size = {}", self.write_buffer_size)?;
+        writeln!(f, "max_write_buffer_number = {}", self.max_write_buffer_number)?;
+        writeln!(f, "bytes_per_sync = {}", self.bytes_per_sync)?;
+        writeln!(f, "block_size = {}", self.block_size)?;
+        writeln!(f, "block_cache_size = {}", self.block_cache_size)?;
+        writeln!(f, "bloom_filter_bits = {}", self.bloom_filter_bits)?;
+        writeln!(f, "compression = \"{}\"", compression)?;
+        writeln!(f, "compaction_style = \"{}\"", compaction_style)?;
+        writeln!(f, "disable_auto_compactions = {}", self.disable_auto_compactions)?;
+        writeln!(f, "max_open_files = {}", self.max_open_files)?;
+        writeln!(f, "use_fsync = {}", self.use_fsync)?;
+        writeln!(f, "sync_writes = {}", self.sync_writes)?;
+        write!(f, "disable_wal = {}", self.disable_wal)
+    }
+}
+
+//value of a config file line without its quotes, only the names of a compression or compaction style are quoted.
+fn unquote<'a>(name: &str, value: &'a str) -> Result<&'a str, String> {
+    let quoted = value.len() >= 2 && value.starts_with('"') && value.ends_with('"');
+    let inner = if quoted { &value[1..value.len() - 1] } else { value };
+    if inner.contains('"') {
+        return Err(format!("{} has unbalanced quotes: {}", name, value));
+    }
+
+    match name {
+        "compression" | "compaction_style" if !quoted => Err(format!("{} must be a quoted string, got {}", name, value)),
+        "compression" | "compaction_style" => Ok(inner),
+        _ if quoted => Err(format!("{} must not be quoted, got {}", name, value)),
+        _ => Ok(inner),
+    }
+}
+
+//integers may use '_' as a digit separator, e.g. 134_217_728.
+fn parse_number<T: ::std::str::FromStr>(name: &str, value: &str) -> Result<T, LedgerError> {
+    match value.replace('_', "").parse() {
+        Ok(n) => Ok(n),
+        Err(_) => Err(LedgerError::Config(format!("{} must be an number, got '{}'", name, value))),
+    }
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, LedgerError> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(LedgerError::Config(format!("{} must be true or false, got '{}'", name, value))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs::File;
+    use std::io::Write;
+    use self::tempdir::TempDir;
+    use super::DbConfig;
+    use super::super::ledger::open_ledger;
+
+    #[test]
+    fn synced_writes_without_the_wal_are_refused() {
+        let dir = TempDir::new("config").unwrap();
+        let path = dir.path().join("token_poc.conf");
+        File::create(&path).unwrap().write_all(b"sync_writes = true\ndisable_wal = true\n").unwrap();
+        assert!(DbConfig::load(path.to_str().unwrap()).is_err());
+
+        let mut config = DbConfig::default();
+        config.set("sync_writes", "true").unwrap();
+        config.set("disable_wal", "true").unwrap();
+        assert!(config.validate().is_err());
+        assert!(open_ledger(dir.path().join("storage").to_str().unwrap(), &config).is_err());
+
+        config.set("sync_writes", "false").unwrap();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn only_option_names_are_quoted() {
+        let dir = TempDir::new("config").unwrap();
+        let path = dir.path().join("token_poc.conf");
+        let load = |text: &str| {
+            File::create(&path).unwrap().write_all(text.as_bytes()).unwrap();
+            DbConfig::load(path.to_str().unwrap())
+        };
+
+        let config = load("compression = \"lz4\" # fast\nparallelism = 4\n").unwrap();
+        assert_eq!(config.parallelism, 4);
+        assert_eq!(load(&format!("{}", config)).unwrap(), config);
+
+        assert!(load("parallelism = \"8\"\n").is_err());
+        assert!(load("use_fsync = \"true\"\n").is_err());
+        assert!(load("compression = lz4\n").is_err());
+        assert!(load("compression = \"lz4\n").is_err());
+        assert!(load("compression = \"lz\"4\"\n").is_err());
+        assert!(load("[rocksdb]\nparallelism = 8\n").is_err());
+    }
+}
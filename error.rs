@@ -0,0 +1,102 @@
+extern crate exonum_rocksdb;
+
+use std::error;
+use std::fmt;
+use std::io;
+
+//failure kinds of the token ledger, so callers can match on them instead of parsing strings.
+#[derive(Debug)]
+pub enum LedgerError {
+    //caller passed an empty id, zero amount or an unknown command.
+    InvalidInput(&'static str),
+    //the db haven't initailized with super account and total supply.
+    NotInitialized,
+    //init on a db that already has a super account, it would reset the supply under the end user balances.
+    AlreadyInitialized,
+    InsufficientBalance { account: String, balance: usize, requested: usize },
+    AccountNotFound(String),
+    TokenNotOwned { account: String, token_id: usize },
+    InsufficientAllowance { owner: String, spender: String, allowance: usize, requested: usize },
+    //the account is not allowed to run the operation, e.g. mint by a non super account.
+    PermissionDenied(String),
+    //super account froze the account, it can neither send nor receive tokens.
+    AccountFrozen(String),
+    //the sender has a public key and the transfer is not signed by it.
+    BadSignature(String),
+    //the nonce of a signed transfer is not greater than the last one the sender used.
+    NonceReplayed { account: String, nonce: usize, last: usize },
+    //the key is held by a concurrent transfer, retry the whole operation.
+    Conflict(String),
+    //operational problem reported by RocksDB, the underlying cause is kept.
+    Storage(exonum_rocksdb::Error),
+    //file system problem outside of RocksDB, e.g. writing the metadata of a checkpoint.
+    Io(io::Error),
+    //a stored value could not be decoded.
+    Codec(&'static str),
+    //the config file or an option override is not valid.
+    Config(String),
+    //recovery found problems it could not fix when the ledger was opened, writes are refused until they are fixed.
+    RecoveryFailed,
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LedgerError::InvalidInput(reason) => write!(f, "invalid input: {}", reason),
+            LedgerError::NotInitialized => write!(f, "the database haven't initailized with super account and total supply"),
+            LedgerError::AlreadyInitialized => write!(f, "the database is initailized already, rotate the super account instead"),
+            LedgerError::InsufficientBalance { ref account, balance, requested } => {
+                write!(f, "'{}' does not have enough tokens: balance {}, requested {}", account, balance, requested)
+            },
+            LedgerError::AccountNotFound(ref account) => write!(f, "account '{}' not found", account),
+            LedgerError::TokenNotOwned { ref account, token_id } => write!(f, "'{}' does not own token {}", account, token_id),
+            LedgerError::InsufficientAllowance { ref owner, ref spender, allowance, requested } => {
+                write!(f, "'{}' is allowed to take {} tokens of '{}', requested {}", spender, allowance, owner, requested)
+            },
+            LedgerError::PermissionDenied(ref account) => write!(f, "'{}' is not allowed to do this operation", account),
+            LedgerError::AccountFrozen(ref account) => write!(f, "account '{}' is frozen", account),
+            LedgerError::BadSignature(ref account) => write!(f, "transfer is not signed by the key of '{}'", account),
+            LedgerError::NonceReplayed { ref account, nonce, last } => {
+                write!(f, "nonce {} of '{}' is used already, it must be greater than {}", nonce, account, last)
+            },
+            LedgerError::Conflict(ref key) => write!(f, "'{}' is busy with a concurrent operation, retry later", key),
+            LedgerError::Storage(ref e) => write!(f, "operational problem encountered: {}", e),
+            LedgerError::Io(ref e) => write!(f, "file system problem encountered: {}", e),
+            LedgerError::Codec(what) => write!(f, "cannot decode {}", what),
+            LedgerError::Config(ref reason) => write!(f, "bad config: {}", reason),
+            LedgerError::RecoveryFailed => write!(f, "recovery of the ledger failed, only reads are served"),
+        }
+    }
+}
+
+impl LedgerError {
+    //true if the same call may succeed when retried as is.
+    pub fn is_retryable(&self) -> bool {
+        match *self {
+            LedgerError::Conflict(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl error::Error for LedgerError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            LedgerError::Storage(ref e) => Some(e),
+            LedgerError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<exonum_rocksdb::Error> for LedgerError {
+    fn from(e: exonum_rocksdb::Error) -> LedgerError {
+        LedgerError::Storage(e)
+    }
+}
+
+impl From<io::Error> for LedgerError {
+    fn from(e: io::Error) -> LedgerError {
+        LedgerError::Io(e)
+    }
+}
@@ -0,0 +1,255 @@
+extern crate exonum_rocksdb;
+extern crate bincode;
+
+use std::result::Result;
+use self::exonum_rocksdb::WriteBatch;
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::{self, account_key, meta_key, escrow_key};
+use super::lock::lock_keys;
+use super::token_set::TokenSet;
+use super::owner_index;
+use super::auth;
+use super::fee;
+use super::freeze::{check_not_frozen, is_frozen};
+use super::registry::check_registered;
+use super::journal::{self, JournalKind};
+use super::account::{get_super_account_id, usize_bytes, load_account, take_amount, transfer_lock_keys};
+
+//time-locked transfer: tokens leave the sender's account into an escrow record, after the deadline they are released
+//to the receiver, before it the sender can cancel and get them back. escrowed tokens count toward neither balance,
+//the owner index keeps pointing them to the sender until the escrow is settled.
+
+#[derive(Debug, Clone)]
+pub struct Escrow {
+    pub id: usize,
+    pub from: String,
+    pub to: String,
+    //seconds since unix epoch, the escrow is released at or after it.
+    pub deadline: u64,
+    pub tokens: TokenSet,
+}
+
+//lock amount tokens of `from` until deadline, returns the id of the escrow.
+//the fee is paid to the collector when the escrow is created, a cancelled escrow does not refund it.
+pub fn create_escrow(db: &Ledger, asset: &String, from: &String, to: &String, amount: usize, deadline: u64) -> Result<usize, LedgerError> {
+    keys::check_asset(asset)?;
+
+    if from.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty sender id"));
+    }
+
+    if to.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty receiver id"));
+    }
+
+    if amount == 0 {
+        return Err(LedgerError::InvalidInput("amount to escrow must be greater than zero"));
+    }
+
+    if deadline <= journal::now_secs() {
+        return Err(LedgerError::InvalidInput("escrow deadline must be in the future"));
+    }
+
+    let super_id = get_super_account_id(db, asset)?;
+    if super_id == *from || super_id == *to {
+        return Err(LedgerError::InvalidInput("super account cannot take part in an escrow"));
+    }
+
+    //there is no signed variant of it yet, so it cannot spend from an account with a public key.
+    auth::check_unsigned(db, asset, from)?;
+
+    let mut lock_list = transfer_lock_keys(db, asset, from, to)?;
+    lock_list.push(meta_key(asset, keys::ESCROW_SEQ));
+    let _locks = lock_keys(db, lock_list)?;
+    check_not_frozen(db, asset, from)?;
+    check_not_frozen(db, asset, to)?;
+    check_registered(db, asset, to)?;
+
+    let mut sender_balance = load_account(db, asset, from)?;
+    let tokens = take_amount(&mut sender_balance, from, amount)?;
+    let fee = fee::take_fee(db, asset, from, &mut sender_balance, amount)?;
+    let id = next_escrow_id(db, asset)?;
+
+    let escrow = Escrow {
+        id: id,
+        from: from.clone(),
+        to: to.clone(),
+        deadline: deadline,
+        tokens: tokens,
+    };
+
+    //atomic commit: sender's remaining tokens, the escrow record, the fee and the journal.
+    let mut batch = WriteBatch::default();
+    batch.put(&account_key(asset, from), &sender_balance.to_bytes())?;
+    batch.put(&escrow_key(asset, id), &encode_escrow(&escrow))?;
+    batch.put(&meta_key(asset, keys::ESCROW_SEQ), &usize_bytes(id + 1))?;
+
+    //unlike the escrowed tokens, the fee tokens change owner right away.
+    let _fee_lock = match fee {
+        Some((ref collector, ref fee_tokens)) => {
+            check_not_frozen(db, asset, collector)?;
+            batch.merge(&account_key(asset, collector), &fee_tokens.to_bytes())?;
+            owner_index::unindex(db, &mut batch, asset, from, fee_tokens)?;
+            owner_index::index_owned(&mut batch, asset, fee_tokens, collector)?;
+            Some(fee::count_fee(db, &mut batch, asset, fee_tokens.len())?)
+        },
+        None => None,
+    };
+
+    let mut journal = journal::begin(db)?;
+    journal.append(&mut batch, JournalKind::Escrow, asset, from, to, &escrow.tokens, None)?;
+    if let Some((ref collector, ref fee_tokens)) = fee {
+        journal.append(&mut batch, JournalKind::Fee, asset, from, collector, fee_tokens, None)?;
+    }
+    db.write(batch)?;
+
+    println!("{} tokens of '{}' are escrowed for '{}' until {} as escrow #{}.", amount, from, to, deadline, id);
+    if let Some((ref collector, ref fee_tokens)) = fee {
+        println!("'{}' paid a fee of {} tokens to '{}'.", from, fee_tokens.len(), collector);
+    }
+    Ok(id)
+}
+
+//give escrowed tokens back to the sender, only the sender can cancel and only before the deadline.
+pub fn cancel_escrow(db: &Ledger, asset: &String, account: &String, id: usize) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
+
+    let escrow = match get_escrow(db, asset, id)? {
+        Some(escrow) => escrow,
+        None => return Err(LedgerError::InvalidInput("no such pending escrow")),
+    };
+
+    if escrow.from != *account {
+        return Err(LedgerError::PermissionDenied(account.clone()));
+    }
+
+    auth::check_unsigned(db, asset, account)?;
+
+    let _locks = lock_keys(db, vec![escrow_key(asset, id), account_key(asset, account)])?;
+    check_not_frozen(db, asset, account)?;
+
+    //settled by a concurrent call while waiting for the locks.
+    let escrow = match get_escrow(db, asset, id)? {
+        Some(escrow) => escrow,
+        None => return Err(LedgerError::InvalidInput("no such pending escrow")),
+    };
+
+    if escrow.deadline <= journal::now_secs() {
+        return Err(LedgerError::InvalidInput("escrow is due, settle it instead"));
+    }
+
+    //the owner index still points the tokens to the sender, only the balance moves back.
+    let mut batch = WriteBatch::default();
+    batch.delete(&escrow_key(asset, id))?;
+    batch.merge(&account_key(asset, account), &escrow.tokens.to_bytes())?;
+    let _seq_lock = journal::append(db, &mut batch, JournalKind::Refund, asset, &escrow.from, &escrow.to, &escrow.tokens, None)?;
+    db.write(batch)?;
+
+    println!("Escrow #{} is cancelled, {} tokens are back to '{}'.", id, escrow.tokens.len(), account);
+    Ok(())
+}
+
+//release every escrow whose deadline has passed to its receiver, returns how many were released.
+//escrows of a frozen account are left pending until it is unfrozen.
+pub fn settle_escrows(db: &Ledger, asset: &String) -> Result<usize, LedgerError> {
+    keys::check_asset(asset)?;
+
+    let now = journal::now_secs();
+    let mut settled = 0;
+    for escrow in pending_escrows(db, asset)? {
+        if escrow.deadline > now {
+            continue;
+        }
+
+        let _locks = lock_keys(db, vec![escrow_key(asset, escrow.id), account_key(asset, &escrow.from), account_key(asset, &escrow.to)])?;
+        if is_frozen(db, asset, &escrow.from)? || is_frozen(db, asset, &escrow.to)? {
+            println!("Escrow #{} is left pending, its account is frozen.", escrow.id);
+            continue;
+        }
+
+        //cancelled by a concurrent call while waiting for the locks.
+        if get_escrow(db, asset, escrow.id)?.is_none() {
+            continue;
+        }
+
+        let mut batch = WriteBatch::default();
+        batch.delete(&escrow_key(asset, escrow.id))?;
+        batch.merge(&account_key(asset, &escrow.to), &escrow.tokens.to_bytes())?;
+        owner_index::index_transfer(db, &mut batch, asset, &escrow.from, &escrow.to, &escrow.tokens)?;
+        let _seq_lock = journal::append(db, &mut batch, JournalKind::Release, asset, &escrow.from, &escrow.to, &escrow.tokens, None)?;
+        db.write(batch)?;
+
+        println!("Escrow #{} released {} tokens to '{}'.", escrow.id, escrow.tokens.len(), escrow.to);
+        settled += 1;
+    }
+    Ok(settled)
+}
+
+pub fn get_escrow(db: &Ledger, asset: &String, id: usize) -> Result<Option<Escrow>, LedgerError> {
+    match db.get(&escrow_key(asset, id))? {
+        Some(bytes) => Ok(Some(decode_escrow(id, &bytes)?)),
+        None => Ok(None),
+    }
+}
+
+//every escrow not settled yet, oldest first.
+pub fn pending_escrows(db: &Ledger, asset: &String) -> Result<Vec<Escrow>, LedgerError> {
+    keys::check_asset(asset)?;
+
+    let prefix = keys::asset_prefix(keys::ESCROW_PREFIX, asset);
+    let mut escrows: Vec<Escrow> = Vec::new();
+    let mut iter = db.raw_iterator();
+    iter.seek(&prefix);
+    while iter.valid() {
+        let key = iter.key().unwrap();
+        if !key.starts_with(&prefix) {
+            break;
+        }
+
+        let id = match keys::from_be_bytes(&key[prefix.len()..]) {
+            Some(id) => id,
+            None => return Err(LedgerError::Codec("escrow key")),
+        };
+        escrows.push(decode_escrow(id, &iter.value().unwrap())?);
+        iter.next();
+    }
+    Ok(escrows)
+}
+
+fn next_escrow_id(db: &Ledger, asset: &String) -> Result<usize, LedgerError> {
+    match db.get(&meta_key(asset, keys::ESCROW_SEQ))? {
+        Some(bytes) => {
+            match bincode::serde::deserialize(&bytes) {
+                Ok(id) => Ok(id),
+                Err(_) => Err(LedgerError::Codec("escrow sequence number")),
+            }
+        },
+        None => Ok(0),
+    }
+}
+
+fn encode_escrow(escrow: &Escrow) -> Vec<u8> {
+    let value = (&escrow.from, &escrow.to, escrow.deadline, escrow.tokens.ranges());
+    bincode::serde::serialize(&value, bincode::SizeLimit::Infinite).unwrap()
+}
+
+pub(crate) fn decode_escrow(id: usize, bytes: &[u8]) -> Result<Escrow, LedgerError> {
+    let value: (String, String, u64, Vec<(usize, usize)>) = match bincode::serde::deserialize(bytes) {
+        Ok(value) => value,
+        Err(_) => return Err(LedgerError::Codec("escrow")),
+    };
+
+    let mut tokens = TokenSet::new();
+    for &(start, end) in &value.3 {
+        tokens.insert_range(start, end);
+    }
+
+    Ok(Escrow {
+        id: id,
+        from: value.0,
+        to: value.1,
+        deadline: value.2,
+        tokens: tokens,
+    })
+}
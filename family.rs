@@ -0,0 +1,130 @@
+extern crate exonum_rocksdb;
+
+use std::result::Result;
+use self::exonum_rocksdb::WriteBatch;
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys;
+use super::recovery::{self, Intent};
+
+//the ledger's keys are grouped into families by namespace, so one kind of data can be counted or reset on its own,
+//e.g. the bench resets every family before a write run instead of dropping the whole db.
+//they are not rocksdb column families: DB::open_cf of exonum_rocksdb 0.7 passes a fresh rocksdb_options_create()
+//for every column family it opens, the default one included, and cannot be given options per family. so once a
+//db has a second column family, the balances lose the merge operator on the next start and every family the
+//options of the config file. the namespaces keep the families apart within the default column family instead,
+//moving them to real column families needs a binding that takes options per column family.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Family {
+    //ledger and asset meta, and the state kept per account besides its tokens: public keys, nonces, allowances,
+    //frozen flags, pending escrows and registered accounts.
+    Meta,
+    //token sets of end user accounts, the only keys written by merge.
+    Balances,
+    //journal records and the history and transaction id entries pointing into them.
+    Journal,
+    //owner index of the token ranges.
+    Indexes,
+}
+
+pub const FAMILIES: [Family; 4] = [Family::Meta, Family::Balances, Family::Journal, Family::Indexes];
+
+impl Family {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Family::Meta => "meta",
+            Family::Balances => "balances",
+            Family::Journal => "journal",
+            Family::Indexes => "indexes",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Family> {
+        FAMILIES.iter().find(|family| family.name() == name).cloned()
+    }
+
+    //key namespaces the family is made of, see the key layout in keys.rs.
+    pub fn namespaces(&self) -> &'static [&'static [u8]] {
+        match *self {
+            Family::Meta => &[keys::META_PREFIX, keys::PUBLIC_KEY_PREFIX, keys::NONCE_PREFIX, keys::ALLOWANCE_PREFIX,
+                              keys::FROZEN_PREFIX, keys::ESCROW_PREFIX, keys::REGISTRY_PREFIX],
+            Family::Balances => &[keys::ACCOUNT_PREFIX],
+            Family::Journal => &[keys::JOURNAL_PREFIX, keys::HISTORY_PREFIX, keys::TX_ID_PREFIX],
+            Family::Indexes => &[keys::OWNER_INDEX_PREFIX],
+        }
+    }
+}
+
+//family of a key, None for a key outside of every namespace, e.g. one written before the key migration.
+pub fn family_of(key: &[u8]) -> Option<Family> {
+    FAMILIES.iter().find(|family| family.namespaces().iter().any(|namespace| key.starts_with(namespace))).cloned()
+}
+
+//number of keys in every family.
+pub fn family_sizes(db: &Ledger) -> Result<Vec<(Family, usize)>, LedgerError> {
+    let mut sizes: Vec<(Family, usize)> = Vec::new();
+    for family in FAMILIES.iter() {
+        let mut count: usize = 0;
+        for namespace in family.namespaces() {
+            let mut iter = db.raw_iterator();
+            iter.seek(namespace);
+            while iter.valid() && iter.key().unwrap().starts_with(namespace) {
+                count += 1;
+                iter.next();
+            }
+        }
+        sizes.push((*family, count));
+    }
+    Ok(sizes)
+}
+
+//delete every key of the family in one atomic batch, returns the number of keys deleted.
+//the other families are left as they are, reset all of them to start the ledger over.
+//meta holds the journal sequence number, it is only reset once the journal is empty, otherwise the next record
+//would be written over the first one.
+pub fn reset_family(db: &Ledger, family: Family) -> Result<usize, LedgerError> {
+    if family == Family::Meta && !is_empty(db, Family::Journal) {
+        return Err(LedgerError::InvalidInput("reset the journal before meta, meta holds its sequence number"));
+    }
+
+    let mut batch = WriteBatch::default();
+    let mut deleted: usize = 0;
+    for namespace in family.namespaces() {
+        let mut iter = db.raw_iterator();
+        iter.seek(namespace);
+        while iter.valid() {
+            let key = iter.key().unwrap();
+            if !key.starts_with(namespace) {
+                break;
+            }
+            batch.delete(&key)?;
+            deleted += 1;
+            iter.next();
+        }
+    }
+    db.write(batch)?;
+
+    println!("Family '{}' is reset, {} keys deleted.", family.name(), deleted);
+    Ok(deleted)
+}
+
+fn is_empty(db: &Ledger, family: Family) -> bool {
+    family.namespaces().iter().all(|namespace| {
+        let mut iter = db.raw_iterator();
+        iter.seek(namespace);
+        !(iter.valid() && iter.key().unwrap().starts_with(namespace))
+    })
+}
+
+//reset every family, only keys outside of the ledger's namespaces are left.
+//the families are deleted in one batch each, the intent recorded first makes recovery finish an interrupted reset.
+//meta goes last, its batch deletes the intent along with it.
+pub fn reset_ledger(db: &Ledger) -> Result<usize, LedgerError> {
+    let _intent_lock = recovery::begin_intent(db, &Intent::ResetLedger)?;
+    let mut deleted: usize = 0;
+    for family in FAMILIES.iter().rev() {
+        deleted += reset_family(db, *family)?;
+    }
+    Ok(deleted)
+}
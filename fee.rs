@@ -0,0 +1,99 @@
+extern crate exonum_rocksdb;
+extern crate bincode;
+
+use std::result::Result;
+use self::exonum_rocksdb::WriteBatch;
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::{self, meta_key};
+use super::lock::{lock_keys, KeyLocks};
+use super::token_set::TokenSet;
+use super::account::usize_bytes;
+
+//transfer fees of an asset, registered by init. end user transfers and escrows pay them in tokens on top of the amount,
+//the fee tokens are moved to the collector account in the same batch as the transfer.
+//roll outs from super account are free.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeSchedule {
+    pub flat: usize,
+    pub per_token: usize,
+    pub collector: String,
+}
+
+impl FeeSchedule {
+    //a fee that does not fit in usize can never be paid, it is refused instead of wrapping around.
+    pub fn fee_for(&self, amount: usize) -> Result<usize, LedgerError> {
+        match self.per_token.checked_mul(amount).and_then(|fee| fee.checked_add(self.flat)) {
+            Some(fee) => Ok(fee),
+            None => Err(LedgerError::InvalidInput("fee of the transfer is too large")),
+        }
+    }
+}
+
+pub fn fee_schedule(db: &Ledger, asset: &String) -> Result<Option<FeeSchedule>, LedgerError> {
+    match db.get(&meta_key(asset, keys::FEE_SCHEDULE))? {
+        Some(bytes) => {
+            let value: (usize, usize, String) = match bincode::serde::deserialize(&bytes) {
+                Ok(value) => value,
+                Err(_) => return Err(LedgerError::Codec("fee schedule")),
+            };
+            Ok(Some(FeeSchedule { flat: value.0, per_token: value.1, collector: value.2 }))
+        },
+        None => Ok(None),
+    }
+}
+
+//tokens paid as fees so far.
+pub fn fees_collected(db: &Ledger, asset: &String) -> Result<usize, LedgerError> {
+    match db.get(&meta_key(asset, keys::FEES_COLLECTED))? {
+        Some(bytes) => {
+            match bincode::serde::deserialize(&bytes) {
+                Ok(value) => Ok(value),
+                Err(_) => Err(LedgerError::Codec("fees collected")),
+            }
+        },
+        None => Ok(0),
+    }
+}
+
+pub(crate) fn put_fee_schedule(batch: &mut WriteBatch, asset: &String, fees: &FeeSchedule) -> Result<(), LedgerError> {
+    let value = bincode::serde::serialize(&(fees.flat, fees.per_token, &fees.collector), bincode::SizeLimit::Infinite).unwrap();
+    batch.put(&meta_key(asset, keys::FEE_SCHEDULE), &value)?;
+    batch.put(&meta_key(asset, keys::FEES_COLLECTED), &usize_bytes(0))?;
+    Ok(())
+}
+
+//take the fee of moving amount tokens out of the sender's remaining tokens, returns the collector and the fee tokens.
+//None if the asset has no fee, the fee is zero or the sender is the collector itself.
+pub(crate) fn take_fee(db: &Ledger, asset: &String, from: &String, balance: &mut TokenSet, amount: usize) -> Result<Option<(String, TokenSet)>, LedgerError> {
+    let fees = match fee_schedule(db, asset)? {
+        Some(fees) => fees,
+        None => return Ok(None),
+    };
+
+    let fee = fees.fee_for(amount)?;
+    if fee == 0 || fees.collector == *from {
+        return Ok(None);
+    }
+
+    match balance.split_off_last(fee) {
+        Some(fee_tokens) => Ok(Some((fees.collector, fee_tokens))),
+        None => {
+            Err(LedgerError::InsufficientBalance {
+                account: from.clone(),
+                balance: balance.len() + amount,
+                requested: amount.saturating_add(fee),
+            })
+        },
+    }
+}
+
+//lock the fee counter and add fee to it in batch, keep the lock alive until the batch is written.
+//take it before the journal writer, which is always the last lock of a batch.
+pub(crate) fn count_fee(db: &Ledger, batch: &mut WriteBatch, asset: &String, fee: usize) -> Result<KeyLocks, LedgerError> {
+    let fee_lock = lock_keys(db, vec![meta_key(asset, keys::FEES_COLLECTED)])?;
+    let collected = fees_collected(db, asset)?;
+    batch.put(&meta_key(asset, keys::FEES_COLLECTED), &usize_bytes(collected + fee))?;
+    Ok(fee_lock)
+}
@@ -0,0 +1,135 @@
+use std::result::Result;
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::{self, account_key, frozen_key};
+use super::lock::lock_keys;
+use super::account::get_super_account_id;
+use super::auth;
+
+//super account can freeze a compromised account, a frozen account can neither send, receive, burn
+//nor collect fees until it is unfrozen.
+
+pub fn freeze(db: &Ledger, asset: &String, admin: &String, account: &String) -> Result<(), LedgerError> {
+    check_admin(db, asset, admin, account)?;
+
+    //transfers check the flag while holding the account's lock, so none of them is half way through.
+    let _locks = lock_keys(db, vec![account_key(asset, account)])?;
+    db.put(&frozen_key(asset, account), &[])?;
+
+    println!("'{}' is frozen.", account);
+    Ok(())
+}
+
+pub fn unfreeze(db: &Ledger, asset: &String, admin: &String, account: &String) -> Result<(), LedgerError> {
+    check_admin(db, asset, admin, account)?;
+
+    let _locks = lock_keys(db, vec![account_key(asset, account)])?;
+    db.delete(&frozen_key(asset, account))?;
+
+    println!("'{}' is unfrozen.", account);
+    Ok(())
+}
+
+pub fn is_frozen(db: &Ledger, asset: &String, account: &String) -> Result<bool, LedgerError> {
+    Ok(db.get(&frozen_key(asset, account))?.is_some())
+}
+
+//every frozen account of the asset, in key order.
+pub fn frozen_accounts(db: &Ledger, asset: &String) -> Result<Vec<String>, LedgerError> {
+    keys::check_asset(asset)?;
+
+    let prefix = keys::asset_prefix(keys::FROZEN_PREFIX, asset);
+    let mut accounts: Vec<String> = Vec::new();
+    let mut iter = db.raw_iterator();
+    iter.seek(&prefix);
+    while iter.valid() {
+        let key = iter.key().unwrap();
+        if !key.starts_with(&prefix) {
+            break;
+        }
+
+        match String::from_utf8(key[prefix.len()..].to_vec()) {
+            Ok(account) => accounts.push(account),
+            Err(_) => return Err(LedgerError::Codec("frozen account key")),
+        }
+        iter.next();
+    }
+    Ok(accounts)
+}
+
+//caller must hold the lock of the account.
+pub(crate) fn check_not_frozen(db: &Ledger, asset: &String, account: &String) -> Result<(), LedgerError> {
+    if is_frozen(db, asset, account)? {
+        return Err(LedgerError::AccountFrozen(account.clone()));
+    }
+    Ok(())
+}
+
+fn check_admin(db: &Ledger, asset: &String, admin: &String, account: &String) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
+
+    if account.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty account id"));
+    }
+
+    let super_id = get_super_account_id(db, asset)?;
+    if super_id != *admin {
+        return Err(LedgerError::PermissionDenied(admin.clone()));
+    }
+    auth::check_unsigned(db, asset, admin)?;
+
+    if super_id == *account {
+        return Err(LedgerError::InvalidInput("super account cannot be frozen"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use self::tempdir::TempDir;
+    use super::{freeze, unfreeze};
+    use super::super::ledger::open_ledger;
+    use super::super::config::DbConfig;
+    use super::super::error::LedgerError;
+    use super::super::fee::FeeSchedule;
+    use super::super::bootstrap::init_super_account;
+    use super::super::account::{get_balance, transfer, transfer_batch};
+    use super::super::supply::{burn, burn_tokens};
+    use super::super::escrow::create_escrow;
+    use super::super::journal::now_secs;
+
+    fn frozen<T: ::std::fmt::Debug>(result: Result<T, LedgerError>) {
+        match result {
+            Err(LedgerError::AccountFrozen(_)) => {},
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn frozen_accounts_cannot_burn_or_collect_fees() {
+        let dir = TempDir::new("frozen").unwrap();
+        let db = open_ledger(dir.path().to_str().unwrap(), &DbConfig::default()).unwrap();
+        let asset = "default".to_string();
+        let (s, a, b, c) = ("S".to_string(), "a".to_string(), "b".to_string(), "c".to_string());
+        let fees = FeeSchedule { flat: 1, per_token: 0, collector: c.clone() };
+        init_super_account(&db, &asset, &s, 1000, Some(&fees)).unwrap();
+        transfer(&db, &asset, &s, &a, 10, None, None).unwrap();
+
+        freeze(&db, &asset, &s, &a).unwrap();
+        frozen(burn(&db, &asset, &a, 1));
+        frozen(burn_tokens(&db, &asset, &a, &[0]));
+        unfreeze(&db, &asset, &s, &a).unwrap();
+
+        freeze(&db, &asset, &s, &c).unwrap();
+        frozen(transfer(&db, &asset, &a, &b, 2, None, None));
+        frozen(transfer_batch(&db, &asset, &[(a.clone(), b.clone(), 2)]));
+        frozen(create_escrow(&db, &asset, &a, &b, 2, now_secs() + 3600));
+        assert_eq!(get_balance(&db, &asset, &a).unwrap(), 10);
+
+        unfreeze(&db, &asset, &s, &c).unwrap();
+        transfer(&db, &asset, &a, &b, 2, None, None).unwrap();
+        assert_eq!(get_balance(&db, &asset, &c).unwrap(), 1);
+    }
+}
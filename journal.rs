@@ -0,0 +1,400 @@
+extern crate exonum_rocksdb;
+extern crate bincode;
+
+use std::result::Result;
+use std::time::{SystemTime, UNIX_EPOCH};
+use self::exonum_rocksdb::WriteBatch;
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::{self, journal_key, history_key, ledger_meta_key, tx_id_key, account_key, meta_key};
+use super::lock::{lock_keys, KeyLocks};
+use super::token_set::{TokenSet, balance_from_bytes};
+use super::account::usize_bytes;
+
+//append only journal of every balance change. a record is written in the same batch as the change it describes,
+//so the journal never misses a committed operation nor holds one that was not committed.
+//every account a record touches gets an entry in the history index pointing back to the record.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JournalKind {
+    Rollout,
+    Transfer,
+    Mint,
+    Burn,
+    //tokens of `from` locked in an escrow for `to`.
+    Escrow,
+    //escrowed tokens handed to `to` after the deadline.
+    Release,
+    //escrowed tokens given back to `from` on cancellation.
+    Refund,
+    //fee of a transfer paid by `from` to the fee collector.
+    Fee,
+    //super account authority and the ids it still holds moved from `from` to `to`.
+    Rotation,
+}
+
+#[derive(Debug, Clone)]
+pub struct JournalRecord {
+    pub seq: usize,
+    //seconds since unix epoch.
+    pub timestamp: u64,
+    pub kind: JournalKind,
+    pub asset: String,
+    //empty for a mint, tokens come from nowhere.
+    pub from: String,
+    //empty for a burn, tokens go nowhere.
+    pub to: String,
+    pub amount: usize,
+    pub tokens: Vec<(usize, usize)>,
+}
+
+//point of the journal a past balance is read at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JournalPoint {
+    //right after the record with this sequence number.
+    Seq(usize),
+    //right after the last record written at or before this time, seconds since unix epoch.
+    Timestamp(u64),
+}
+
+impl JournalKind {
+    fn to_u8(&self) -> u8 {
+        match *self {
+            JournalKind::Rollout => 0,
+            JournalKind::Transfer => 1,
+            JournalKind::Mint => 2,
+            JournalKind::Burn => 3,
+            JournalKind::Escrow => 4,
+            JournalKind::Release => 5,
+            JournalKind::Refund => 6,
+            JournalKind::Fee => 7,
+            JournalKind::Rotation => 8,
+        }
+    }
+
+    fn from_u8(value: u8) -> Option<JournalKind> {
+        match value {
+            0 => Some(JournalKind::Rollout),
+            1 => Some(JournalKind::Transfer),
+            2 => Some(JournalKind::Mint),
+            3 => Some(JournalKind::Burn),
+            4 => Some(JournalKind::Escrow),
+            5 => Some(JournalKind::Release),
+            6 => Some(JournalKind::Refund),
+            7 => Some(JournalKind::Fee),
+            8 => Some(JournalKind::Rotation),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            JournalKind::Rollout => "rollout",
+            JournalKind::Transfer => "transfer",
+            JournalKind::Mint => "mint",
+            JournalKind::Burn => "burn",
+            JournalKind::Escrow => "escrow",
+            JournalKind::Release => "release",
+            JournalKind::Refund => "refund",
+            JournalKind::Fee => "fee",
+            JournalKind::Rotation => "rotation",
+        }
+    }
+}
+
+//hands out sequence numbers to the records of one batch, holding the lock of the sequence number.
+//keep it alive until the batch is written, so sequence numbers are handed out once and committed in order.
+pub(crate) struct JournalWriter {
+    _seq_lock: KeyLocks,
+    next_seq: usize,
+}
+
+pub(crate) fn begin(db: &Ledger) -> Result<JournalWriter, LedgerError> {
+    let seq_lock = lock_keys(db, vec![ledger_meta_key(keys::JOURNAL_SEQ)])?;
+    let next_seq = next_seq(db)?;
+    Ok(JournalWriter { _seq_lock: seq_lock, next_seq: next_seq })
+}
+
+//put the record of a single change into batch, see JournalWriter::append.
+pub(crate) fn append(db: &Ledger, batch: &mut WriteBatch, kind: JournalKind, asset: &String, from: &String, to: &String, tokens: &TokenSet, tx_id: Option<&String>) -> Result<JournalWriter, LedgerError> {
+    let mut writer = begin(db)?;
+    writer.append(batch, kind, asset, from, to, tokens, tx_id)?;
+    Ok(writer)
+}
+
+impl JournalWriter {
+    //put the record of a change and its history entries into batch.
+    //a client transaction id given with the change is pointed at the record, caller must hold the lock of its key.
+    pub(crate) fn append(&mut self, batch: &mut WriteBatch, kind: JournalKind, asset: &String, from: &String, to: &String, tokens: &TokenSet, tx_id: Option<&String>) -> Result<usize, LedgerError> {
+        let seq = self.next_seq;
+        let record = JournalRecord {
+            seq: seq,
+            timestamp: now_secs(),
+            kind: kind,
+            asset: asset.clone(),
+            from: from.clone(),
+            to: to.clone(),
+            amount: tokens.len(),
+            tokens: tokens.ranges().to_vec(),
+        };
+
+        batch.put(&journal_key(seq), &encode_record(&record))?;
+        for account in &[from, to] {
+            if account.len() > 0 {
+                batch.put(&history_key(asset, account, seq), &[])?;
+            }
+        }
+        if let Some(tx_id) = tx_id {
+            batch.put(&tx_id_key(asset, tx_id), &usize_bytes(seq))?;
+        }
+        //the last put of a batch wins, so it ends up after the last record.
+        batch.put(&ledger_meta_key(keys::JOURNAL_SEQ), &usize_bytes(seq + 1))?;
+        self.next_seq = seq + 1;
+        Ok(seq)
+    }
+}
+
+//record of the change a client transaction id was used for, None if the id is new.
+pub fn find_tx(db: &Ledger, asset: &String, tx_id: &String) -> Result<Option<JournalRecord>, LedgerError> {
+    let seq: usize = match db.get(&tx_id_key(asset, tx_id))? {
+        Some(bytes) => {
+            match bincode::serde::deserialize(&bytes) {
+                Ok(seq) => seq,
+                Err(_) => return Err(LedgerError::Codec("transaction id entry")),
+            }
+        },
+        None => return Ok(None),
+    };
+
+    match record(db, seq)? {
+        Some(record) => Ok(Some(record)),
+        None => Err(LedgerError::Codec("journal record")),
+    }
+}
+
+//journal record with sequence number seq, None if it was never written.
+pub fn record(db: &Ledger, seq: usize) -> Result<Option<JournalRecord>, LedgerError> {
+    match db.get(&journal_key(seq))? {
+        Some(bytes) => Ok(Some(decode_record(&bytes)?)),
+        None => Ok(None),
+    }
+}
+
+//records touching account, oldest first, starting at sequence number cursor.
+//returns at most limit records and the cursor of the next page, None if there is no more.
+pub fn history(db: &Ledger, asset: &String, account: &String, cursor: usize, limit: usize) -> Result<(Vec<JournalRecord>, Option<usize>), LedgerError> {
+    keys::check_asset(asset)?;
+
+    if account.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty account id"));
+    }
+
+    if limit == 0 {
+        return Err(LedgerError::InvalidInput("limit must be greater than zero"));
+    }
+
+    let prefix = keys::history_prefix(asset, account);
+    let mut records: Vec<JournalRecord> = Vec::new();
+    let mut iter = db.raw_iterator();
+    iter.seek(&history_key(asset, account, cursor));
+    while iter.valid() {
+        let key = iter.key().unwrap();
+        if !key.starts_with(&prefix) {
+            break;
+        }
+
+        let seq = match keys::from_be_bytes(&key[prefix.len()..]) {
+            Some(seq) => seq,
+            None => return Err(LedgerError::Codec("history index key")),
+        };
+
+        if records.len() == limit {
+            return Ok((records, Some(seq)));
+        }
+
+        match record(db, seq)? {
+            Some(record) => records.push(record),
+            None => return Err(LedgerError::Codec("journal record")),
+        }
+        iter.next();
+    }
+    Ok((records, None))
+}
+
+//balance of account right after the given point of the journal.
+//it starts from the balance now and takes back every later record touching the account, all read from one snapshot,
+//so balances of a db migrated from before the journal existed are answered too.
+pub fn get_balance_at(db: &Ledger, asset: &String, account: &String, at: JournalPoint) -> Result<usize, LedgerError> {
+    keys::check_asset(asset)?;
+
+    if account.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty account id"));
+    }
+
+    let snapshot = db.snapshot();
+    let super_id = match snapshot.get(&meta_key(asset, keys::SUPER_ACCOUNT))? {
+        Some(value) => {
+            match value.to_utf8() {
+                Some(super_id) => super_id.to_string(),
+                None => return Err(LedgerError::Codec("super account id")),
+            }
+        },
+        None => return Err(LedgerError::NotInitialized),
+    };
+
+    //super account's balance is a counter, end user balances are the header of their token set.
+    let balance: Option<usize> = if super_id == *account {
+        match snapshot.get(&meta_key(asset, keys::SUPER_BALANCE))? {
+            Some(bytes) => {
+                match bincode::serde::deserialize(&bytes) {
+                    Ok(value) => Some(value),
+                    Err(_) => return Err(LedgerError::Codec("super account meta")),
+                }
+            },
+            None => None,
+        }
+    } else {
+        match snapshot.get(&account_key(asset, account))? {
+            Some(bytes) => Some(balance_from_bytes(&bytes)?),
+            None => None,
+        }
+    };
+
+    let prefix = keys::history_prefix(asset, account);
+    //tokens gained and lost by the account after the point.
+    let mut gained: usize = 0;
+    let mut lost: usize = 0;
+    let mut iter = snapshot.raw_iterator();
+    match at {
+        JournalPoint::Seq(seq) => {
+            match seq.checked_add(1) {
+                Some(next) => iter.seek(&history_key(asset, account, next)),
+                None => return Err(LedgerError::InvalidInput("journal sequence number is too large")),
+            }
+        },
+        JournalPoint::Timestamp(_) => iter.seek(&prefix),
+    }
+    while iter.valid() {
+        let key = iter.key().unwrap();
+        if !key.starts_with(&prefix) {
+            break;
+        }
+
+        let seq = match keys::from_be_bytes(&key[prefix.len()..]) {
+            Some(seq) => seq,
+            None => return Err(LedgerError::Codec("history index key")),
+        };
+        let record = match snapshot.get(&journal_key(seq))? {
+            Some(bytes) => decode_record(&bytes)?,
+            None => return Err(LedgerError::Codec("journal record")),
+        };
+        iter.next();
+
+        if let JournalPoint::Timestamp(timestamp) = at {
+            if record.timestamp <= timestamp {
+                continue;
+            }
+        }
+
+        let (record_gained, record_lost) = balance_change(&record, account);
+        gained += record_gained;
+        lost += record_lost;
+    }
+
+    //an account without tokens now is known if it shows up in the journal, e.g. a rotated out super account.
+    if balance.is_none() {
+        let mut iter = snapshot.raw_iterator();
+        iter.seek(&prefix);
+        if !iter.valid() || !iter.key().unwrap().starts_with(&prefix) {
+            return Err(LedgerError::AccountNotFound(account.clone()));
+        }
+    }
+
+    //undo the later records: add back what they took, take back what they gave.
+    match (balance.unwrap_or(0) + lost).checked_sub(gained) {
+        Some(balance) => Ok(balance),
+        None => Err(LedgerError::Codec("balance history")),
+    }
+}
+
+//tokens account gained and lost by the change of record. escrowed tokens count toward neither balance,
+//so an escrow only takes from the sender, a release only gives to the receiver and a refund gives back to the sender.
+fn balance_change(record: &JournalRecord, account: &String) -> (usize, usize) {
+    let mut gained = 0;
+    let mut lost = 0;
+    match record.kind {
+        JournalKind::Refund => {
+            if record.from == *account {
+                gained += record.amount;
+            }
+        },
+        JournalKind::Escrow => {
+            if record.from == *account {
+                lost += record.amount;
+            }
+        },
+        JournalKind::Release => {
+            if record.to == *account {
+                gained += record.amount;
+            }
+        },
+        _ => {
+            if record.from == *account {
+                lost += record.amount;
+            }
+            if record.to == *account {
+                gained += record.amount;
+            }
+        },
+    }
+    (gained, lost)
+}
+
+//seconds since unix epoch.
+pub(crate) fn now_secs() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+fn next_seq(db: &Ledger) -> Result<usize, LedgerError> {
+    match db.get(&ledger_meta_key(keys::JOURNAL_SEQ))? {
+        Some(bytes) => {
+            match bincode::serde::deserialize(&bytes) {
+                Ok(seq) => Ok(seq),
+                Err(_) => Err(LedgerError::Codec("journal sequence number")),
+            }
+        },
+        None => Ok(0),
+    }
+}
+
+fn encode_record(record: &JournalRecord) -> Vec<u8> {
+    let value = (record.seq, record.timestamp, record.kind.to_u8(), &record.asset,
+                 &record.from, &record.to, record.amount, &record.tokens);
+    bincode::serde::serialize(&value, bincode::SizeLimit::Infinite).unwrap()
+}
+
+fn decode_record(bytes: &[u8]) -> Result<JournalRecord, LedgerError> {
+    let value: (usize, u64, u8, String, String, String, usize, Vec<(usize, usize)>) = match bincode::serde::deserialize(bytes) {
+        Ok(value) => value,
+        Err(_) => return Err(LedgerError::Codec("journal record")),
+    };
+
+    let kind = match JournalKind::from_u8(value.2) {
+        Some(kind) => kind,
+        None => return Err(LedgerError::Codec("journal record kind")),
+    };
+
+    Ok(JournalRecord {
+        seq: value.0,
+        timestamp: value.1,
+        kind: kind,
+        asset: value.3,
+        from: value.4,
+        to: value.5,
+        amount: value.6,
+        tokens: value.7,
+    })
+}
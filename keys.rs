@@ -0,0 +1,172 @@
+use std::result::Result;
+use super::error::LedgerError;
+
+//key layout of the ledger, every key is namespaced so an end user account can never collide with a meta key,
+//and every token system (asset) gets its own key range so one db can host many of them:
+//  m:<name>                 meta of the whole ledger, e.g. m:SchemaVersion.
+//  m:<asset>:<name>         meta of an asset, e.g. m:<asset>:SuperAccount, TotalSupply, SuperBalance, Burned, FeeSchedule.
+//  a:<asset>:<account>      token set of an end user account.
+//  o:<asset>:<end>          owner of the token range ending at <end>, big-endian so ranges sort by id.
+//  j:<seq>                  immutable journal record of the ledger, big-endian sequence number.
+//  h:<asset>:<len><account><seq>  history index of an account, the length prefix keeps "x" from matching "x:y".
+//  t:<asset>:<tx id>        journal sequence number of the transfer a client transaction id was used for.
+//  k:<asset>:<account>      ed25519 public key of an account.
+//  n:<asset>:<account>      last nonce used by an account in a signed transfer.
+//  l:<asset>:<len><owner><spender>  amount spender is still allowed to take from owner.
+//  f:<asset>:<account>      present while super account keeps the account frozen.
+//  e:<asset>:<id>           pending escrow, big-endian id so they are settled in creation order.
+//  r:<asset>:<account>      registered account, its creation time and metadata.
+//family.rs groups the namespaces into the meta, balances, journal and indexes families.
+//asset ids cannot contain ':' so the asset segment always ends at the first separator after the prefix.
+pub const META_PREFIX: &'static [u8] = b"m:";
+pub const ACCOUNT_PREFIX: &'static [u8] = b"a:";
+pub const OWNER_INDEX_PREFIX: &'static [u8] = b"o:";
+pub const JOURNAL_PREFIX: &'static [u8] = b"j:";
+pub const HISTORY_PREFIX: &'static [u8] = b"h:";
+pub const TX_ID_PREFIX: &'static [u8] = b"t:";
+pub const PUBLIC_KEY_PREFIX: &'static [u8] = b"k:";
+pub const NONCE_PREFIX: &'static [u8] = b"n:";
+pub const ALLOWANCE_PREFIX: &'static [u8] = b"l:";
+pub const FROZEN_PREFIX: &'static [u8] = b"f:";
+pub const ESCROW_PREFIX: &'static [u8] = b"e:";
+pub const REGISTRY_PREFIX: &'static [u8] = b"r:";
+pub const SEPARATOR: u8 = b':';
+
+pub const SUPER_ACCOUNT: &'static str = "SuperAccount";
+pub const TOTAL_SUPPLY: &'static str = "TotalSupply";
+pub const SUPER_BALANCE: &'static str = "SuperBalance";
+//tokens destroyed so far, their ids stay issued and are never rolled out again.
+pub const BURNED: &'static str = "Burned";
+//(flat, per token, collector) fees of end user transfers, absent if the asset charges none.
+pub const FEE_SCHEDULE: &'static str = "FeeSchedule";
+//tokens paid as fees so far.
+pub const FEES_COLLECTED: &'static str = "FeesCollected";
+//id of the next escrow of an asset.
+pub const ESCROW_SEQ: &'static str = "EscrowSeq";
+//present while transfers to accounts that are not registered are rejected.
+pub const REQUIRE_REGISTERED: &'static str = "RequireRegistered";
+//next journal sequence number of the ledger.
+pub const JOURNAL_SEQ: &'static str = "JournalSeq";
+//written by init and by the key migration, its absence on a non-empty db means un-prefixed keys.
+pub const SCHEMA_VERSION: &'static str = "SchemaVersion";
+//present while a multi-step operation is in progress, see recovery.rs.
+pub const INTENT: &'static str = "Intent";
+//1: namespaced single token system, 2: asset scoped keys.
+pub const CURRENT_SCHEMA_VERSION: usize = 2;
+
+//asset that data written before assets existed is migrated into.
+pub const DEFAULT_ASSET: &'static str = "default";
+
+//meta keys used before the namespaced layout existed.
+pub const LEGACY_META_KEYS: [&'static str; 3] = [SUPER_ACCOUNT, TOTAL_SUPPLY, SUPER_BALANCE];
+
+pub fn check_asset(asset: &str) -> Result<(), LedgerError> {
+    if asset.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty asset id"));
+    }
+
+    if asset.as_bytes().contains(&SEPARATOR) {
+        return Err(LedgerError::InvalidInput("asset id cannot contain ':'"));
+    }
+    Ok(())
+}
+
+//meta key of the whole ledger, not scoped by asset.
+pub fn ledger_meta_key(name: &str) -> Vec<u8> {
+    prefixed(META_PREFIX, name.as_bytes())
+}
+
+pub fn meta_key(asset: &str, name: &str) -> Vec<u8> {
+    prefixed(&asset_prefix(META_PREFIX, asset), name.as_bytes())
+}
+
+pub fn account_key(asset: &str, account: &str) -> Vec<u8> {
+    prefixed(&asset_prefix(ACCOUNT_PREFIX, asset), account.as_bytes())
+}
+
+pub fn owner_index_key(asset: &str, end: usize) -> Vec<u8> {
+    prefixed(&asset_prefix(OWNER_INDEX_PREFIX, asset), &be_bytes(end))
+}
+
+pub fn journal_key(seq: usize) -> Vec<u8> {
+    prefixed(JOURNAL_PREFIX, &be_bytes(seq))
+}
+
+//h:<asset>:<len><account> , the common prefix of all history entries of an account.
+pub fn history_prefix(asset: &str, account: &str) -> Vec<u8> {
+    let mut bytes = prefixed(&asset_prefix(HISTORY_PREFIX, asset), &be_bytes(account.len()));
+    bytes.extend_from_slice(account.as_bytes());
+    bytes
+}
+
+pub fn history_key(asset: &str, account: &str, seq: usize) -> Vec<u8> {
+    prefixed(&history_prefix(asset, account), &be_bytes(seq))
+}
+
+pub fn tx_id_key(asset: &str, tx_id: &str) -> Vec<u8> {
+    prefixed(&asset_prefix(TX_ID_PREFIX, asset), tx_id.as_bytes())
+}
+
+pub fn public_key_key(asset: &str, account: &str) -> Vec<u8> {
+    prefixed(&asset_prefix(PUBLIC_KEY_PREFIX, asset), account.as_bytes())
+}
+
+pub fn nonce_key(asset: &str, account: &str) -> Vec<u8> {
+    prefixed(&asset_prefix(NONCE_PREFIX, asset), account.as_bytes())
+}
+
+pub fn allowance_key(asset: &str, owner: &str, spender: &str) -> Vec<u8> {
+    let mut bytes = prefixed(&asset_prefix(ALLOWANCE_PREFIX, asset), &be_bytes(owner.len()));
+    bytes.extend_from_slice(owner.as_bytes());
+    bytes.extend_from_slice(spender.as_bytes());
+    bytes
+}
+
+pub fn frozen_key(asset: &str, account: &str) -> Vec<u8> {
+    prefixed(&asset_prefix(FROZEN_PREFIX, asset), account.as_bytes())
+}
+
+pub fn escrow_key(asset: &str, id: usize) -> Vec<u8> {
+    prefixed(&asset_prefix(ESCROW_PREFIX, asset), &be_bytes(id))
+}
+
+pub fn registry_key(asset: &str, account: &str) -> Vec<u8> {
+    prefixed(&asset_prefix(REGISTRY_PREFIX, asset), account.as_bytes())
+}
+
+//<namespace><asset>: , the common prefix of all keys of an asset in one namespace.
+pub fn asset_prefix(namespace: &[u8], asset: &str) -> Vec<u8> {
+    let mut bytes = prefixed(namespace, asset.as_bytes());
+    bytes.push(SEPARATOR);
+    bytes
+}
+
+//fixed width big-endian encoding, so byte order of keys follows numeric order.
+pub fn be_bytes(value: usize) -> [u8; 8] {
+    let value = value as u64;
+    let mut bytes = [0u8; 8];
+    for i in 0..8 {
+        bytes[i] = (value >> ((7 - i) * 8)) as u8;
+    }
+    bytes
+}
+
+//decode a fixed width big-endian value written by be_bytes.
+pub fn from_be_bytes(bytes: &[u8]) -> Option<usize> {
+    if bytes.len() != 8 {
+        return None;
+    }
+
+    let mut value: u64 = 0;
+    for &b in bytes {
+        value = (value << 8) | b as u64;
+    }
+    Some(value as usize)
+}
+
+pub fn prefixed(prefix: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(prefix.len() + key.len());
+    bytes.extend_from_slice(prefix);
+    bytes.extend_from_slice(key);
+    bytes
+}
@@ -0,0 +1,130 @@
+extern crate exonum_rocksdb;
+
+use std::path::Path;
+use std::result::Result;
+use self::exonum_rocksdb::{DB, Options, WriteOptions, WriteBatch, MergeOperands, DBVector, DBIterator, DBRawIterator, IteratorMode, Snapshot};
+use super::error::LedgerError;
+use super::config::DbConfig;
+use super::token_set::TokenSet;
+use super::recovery::{self, RecoveryReport};
+
+//handle of an open token storage, every function of the library takes it.
+//balances are written by merge, a db opened without the merge operator below fails every transfer,
+//so the handle is only made by open_ledger. it also runs recovery, writes are refused if recovery failed.
+
+pub struct Ledger {
+    db: DB,
+    recovery: RecoveryReport,
+    //wal settings of config, see DbConfig::write_options.
+    config: DbConfig,
+}
+
+//open or create the token storage at path, with the rocksdb options of config and the merge operator of the balances,
+//then recover it, see recovery.rs.
+pub fn open_ledger(path: &str, config: &DbConfig) -> Result<Ledger, LedgerError> {
+    config.validate()?;
+
+    let mut opts = Options::default();
+    config.apply(&mut opts);
+    opts.create_if_missing(true);
+    //exonum_rocksdb registers the handler for full and partial merges, see balance_merge_handler.
+    opts.set_merge_operator("balance array merge handler", balance_merge_handler);
+
+    let mut ledger = Ledger { db: DB::open(&opts, path)?, recovery: RecoveryReport::default(), config: config.clone() };
+    ledger.recovery = match recovery::recover(&ledger) {
+        Ok(report) => report,
+        Err(e) => {
+            let mut report = RecoveryReport::default();
+            report.problems.push(format!("recovery stopped: {}", e));
+            report
+        },
+    };
+    Ok(ledger)
+}
+
+impl Ledger {
+    //what recovery did when the ledger was opened, and the problems it left if writes are refused.
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery
+    }
+
+    pub fn path(&self) -> &Path {
+        self.db.path()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<DBVector>, exonum_rocksdb::Error> {
+        self.db.get(key)
+    }
+
+    pub fn iterator(&self, mode: IteratorMode) -> DBIterator {
+        self.db.iterator(mode)
+    }
+
+    pub fn raw_iterator(&self) -> DBRawIterator {
+        self.db.raw_iterator()
+    }
+
+    pub fn snapshot<'a>(&'a self) -> Snapshot<'a> {
+        self.db.snapshot()
+    }
+
+    //writes are for the library only, so the ledger invariants hold for whatever a consumer calls.
+    pub(crate) fn put(&self, key: &[u8], value: &[u8]) -> Result<(), LedgerError> {
+        self.check_writable()?;
+        Ok(self.db.put_opt(key, value, &self.write_options())?)
+    }
+
+    pub(crate) fn delete(&self, key: &[u8]) -> Result<(), LedgerError> {
+        self.check_writable()?;
+        Ok(self.db.delete_opt(key, &self.write_options())?)
+    }
+
+    pub(crate) fn write(&self, batch: WriteBatch) -> Result<(), LedgerError> {
+        self.check_writable()?;
+        Ok(self.db.write_opt(batch, &self.write_options())?)
+    }
+
+    //made per write, rocksdb's WriteOptions cannot be shared between the threads of the ledger.
+    fn write_options(&self) -> WriteOptions {
+        self.config.write_options()
+    }
+
+    fn check_writable(&self) -> Result<(), LedgerError> {
+        if !self.recovery.is_ok() {
+            return Err(LedgerError::RecoveryFailed);
+        }
+        Ok(())
+    }
+}
+
+//adding tokens into account by using merge operation for a better performance.
+//rocksdb calls it with the stored value for a full merge, and with none to collapse a stack of operands into one,
+//a union of token sets gives the same result either way.
+//it runs inside a rocksdb callback where a panic aborts the process, so a value that does not decode is reported
+//and left for audit instead: a broken stored value is kept as it is, a broken operand is skipped.
+fn balance_merge_handler(key: &[u8], existing_val: Option<&[u8]>, operands: &mut MergeOperands) -> Vec<u8> {
+    //if account does not existed, start from an empty token set which serializes a zero length header.
+    //a full merge without a stored value gets an empty one.
+    let mut balance = match existing_val {
+        Some(bytes) if bytes.len() > 0 => {
+            match TokenSet::from_bytes(bytes) {
+                Ok(balance) => balance,
+                Err(e) => {
+                    eprintln!("merge into '{}' is dropped: {}", String::from_utf8_lossy(key), e);
+                    return bytes.to_vec();
+                },
+            }
+        },
+        _ => TokenSet::new(),
+    };
+
+    //union the token ranges of every merge operation, adjacent ranges get coalesced so the value stays small.
+    for op in operands {
+        match TokenSet::from_bytes(op) {
+            Ok(patch) => balance.union(&patch),
+            Err(e) => eprintln!("merge operand of '{}' is skipped: {}", String::from_utf8_lossy(key), e),
+        }
+    }
+
+    balance.to_bytes()
+}
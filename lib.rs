@@ -1,5 +1,22 @@
+pub mod error;
+pub mod keys;
+mod lock;
 pub mod bootstrap;
 pub mod snapshot;
 pub mod account;
+pub mod supply;
 pub mod recovery;
-pub mod bench;
\ No newline at end of file
+pub mod bench;
+pub mod token_set;
+pub mod owner_index;
+pub mod journal;
+pub mod auth;
+pub mod allowance;
+pub mod freeze;
+pub mod escrow;
+pub mod fee;
+pub mod audit;
+pub mod registry;
+pub mod config;
+pub mod family;
+pub mod ledger;
\ No newline at end of file
@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+use std::result::Result;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+use super::error::LedgerError;
+use super::ledger::Ledger;
+
+//keys held by in-flight read-then-write operations, tagged with the path of the db they belong to.
+//RocksDB lets only one process open a db at a time, so serializing the writers of this process is enough.
+static HELD_KEYS: Mutex<Vec<(PathBuf, Vec<u8>)>> = Mutex::new(Vec::new());
+static RELEASED: Condvar = Condvar::new();
+
+//how long to wait for a busy key before giving up with a retryable conflict.
+pub const LOCK_TIMEOUT_MS: u64 = 1_000;
+
+//released when dropped, keep it alive until the batch is written.
+pub struct KeyLocks {
+    path: PathBuf,
+    keys: Vec<Vec<u8>>,
+}
+
+//lock all the given keys or none of them, so two operations can never deadlock each other.
+pub fn lock_keys(db: &Ledger, mut keys: Vec<Vec<u8>>) -> Result<KeyLocks, LedgerError> {
+    keys.sort();
+    keys.dedup();
+
+    let path = db.path().to_path_buf();
+    let deadline = Instant::now() + Duration::from_millis(LOCK_TIMEOUT_MS);
+    let mut held = HELD_KEYS.lock().unwrap();
+
+    loop {
+        let busy = held.iter()
+            .find(|entry| entry.0 == path && keys.contains(&entry.1))
+            .map(|entry| entry.1.clone());
+
+        match busy {
+            None => {
+                for key in &keys {
+                    held.push((path.clone(), key.clone()));
+                }
+                return Ok(KeyLocks { path: path, keys: keys });
+            },
+            Some(key) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(LedgerError::Conflict(String::from_utf8_lossy(&key).into_owned()));
+                }
+                held = RELEASED.wait_timeout(held, deadline - now).unwrap().0;
+            },
+        }
+    }
+}
+
+impl Drop for KeyLocks {
+    fn drop(&mut self) {
+        let mut held = HELD_KEYS.lock().unwrap();
+        held.retain(|entry| !(entry.0 == self.path && self.keys.contains(&entry.1)));
+        RELEASED.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use self::tempdir::TempDir;
+    use super::lock_keys;
+    use super::super::ledger::open_ledger;
+    use super::super::config::DbConfig;
+    use super::super::error::LedgerError;
+
+    #[test]
+    fn busy_key_times_out_with_a_retryable_conflict() {
+        let dir = TempDir::new("lock").unwrap();
+        let db = Arc::new(open_ledger(dir.path().to_str().unwrap(), &DbConfig::default()).unwrap());
+        let held = lock_keys(&db, vec![b"x".to_vec(), b"y".to_vec()]).unwrap();
+
+        //all or nothing: z is free but y is not, so z must not stay locked either.
+        match lock_keys(&db, vec![b"z".to_vec(), b"y".to_vec()]) {
+            Err(ref e @ LedgerError::Conflict(_)) => assert!(e.is_retryable()),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("y is held"),
+        }
+        drop(lock_keys(&db, vec![b"z".to_vec()]).unwrap());
+
+        //a waiter gets the key as soon as it is released.
+        let waiter = {
+            let db = db.clone();
+            thread::spawn(move || lock_keys(&db, vec![b"x".to_vec()]).is_ok())
+        };
+        thread::sleep(Duration::from_millis(100));
+        drop(held);
+        assert!(waiter.join().unwrap());
+    }
+}
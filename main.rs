@@ -1,75 +1,169 @@
-extern crate exonum_rocksdb;
 extern crate tempdir;
-extern crate bincode;
 
 extern crate token_id_poc;
 
 use std::env;
+use std::process;
+use std::path::Path;
 use std::time::Instant;
-use exonum_rocksdb::{DB, Options, MergeOperands};
-use token_id_poc::bootstrap::init_super_account;
-use token_id_poc::account::{get_balance, transfer};
+use token_id_poc::bootstrap::{init_super_account, rotate_super_account, migrate_legacy_keys};
+use token_id_poc::account::{get_balance, owner_of, transfer, transfer_tokens, transfer_batch};
+use token_id_poc::supply::{mint, burn, burn_tokens};
 use token_id_poc::bench::bench_test;
+use token_id_poc::keys::DEFAULT_ASSET;
+use token_id_poc::journal::{history, get_balance_at, JournalPoint};
+use token_id_poc::auth::{register_public_key, TransferAuth};
+use token_id_poc::allowance::{approve, allowance, transfer_from};
+use token_id_poc::freeze::{freeze, unfreeze, frozen_accounts};
+use token_id_poc::escrow::{create_escrow, cancel_escrow, settle_escrows};
+use token_id_poc::fee::{FeeSchedule, fee_schedule, fees_collected};
+use token_id_poc::audit::audit;
+use token_id_poc::registry::{create_account, account_info, list_accounts, require_registered};
+use token_id_poc::config::DbConfig;
+use token_id_poc::family::{Family, family_sizes, reset_family, reset_ledger};
+use token_id_poc::ledger::{Ledger, open_ledger};
+use token_id_poc::snapshot::{CheckpointInfo, create_checkpoint, list_checkpoints, restore_checkpoint};
 
-// adding tokens into account by using merge operation for a better performance.
-fn balance_array_merge_handler(_: &[u8], existing_val: Option<&[u8]>, operands: &mut MergeOperands)-> Vec<u8> {
-    //to do estimate and reserve heap for vec for better perf.
-    let mut result: Vec<u8> = Vec::with_capacity(operands.size_hint().0);
-
-    //if account does not existed, just serialized 64bits to hold the lengh of its token array.
-    if existing_val.is_none() {
-        let length: usize = 0;
-        let mut bytes = bincode::serde::serialize(&length, bincode::SizeLimit::Infinite).unwrap();
-        result.append(&mut bytes);
-    } else {
-        result.extend_from_slice(existing_val.unwrap());
+//rocksdb options read on startup when --config is not given.
+const DEFAULT_CONFIG: &'static str = "./token_poc.conf";
+//dir the checkpoints of the token storage are created in.
+const CHECKPOINT_ROOT: &'static str = "./checkpoints";
+
+//open the ledger and print what recovery did, writes return an error if it left problems.
+//exits if the storage cannot be opened at all.
+fn get_db(dir: &str, config: &DbConfig) -> Ledger {
+    //to do better for configuable dir for db storage.
+    let ledger = match open_ledger(dir, config) {
+        Ok(ledger) => ledger,
+        Err(e) => {
+            eprintln!("error: cannot open {}: {}", dir, e);
+            process::exit(1);
+        }
+    };
+    let report = ledger.recovery_report();
+    if !report.is_ok() || report.actions.len() > 0 {
+        println!("Recovery of {}:\n{}", dir, report);
     }
+    ledger
+}
 
-    let mut balance_increased: usize = 0;
+//remove "--asset [asset id]" from args so the positional arguments are the same with or without it.
+//commands work on the default asset when it is not given.
+fn take_asset_arg(args: &mut Vec<String>) -> Result<String, &'static str> {
+    match take_option_arg(args, "--asset") {
+        Ok(Some(asset)) => Ok(asset),
+        Ok(None) => Ok(String::from(DEFAULT_ASSET)),
+        Err(_) => Err("--asset needs an asset id"),
+    }
+}
 
-    //calculate total increasement from the merge operation list, merge arrays by per merge operation.
-    for op in operands {
-       //to do better for hard coded length.
-       let mut patch_balance: usize = bincode::serde::deserialize(&op[0..8]).unwrap();
-       balance_increased += patch_balance;
-       result.extend_from_slice(&op[8..]);
+//remove "[name] [value]" from args and return the value, None if the option is not given.
+fn take_option_arg(args: &mut Vec<String>, name: &str) -> Result<Option<String>, ()> {
+    match args.iter().position(|arg| arg == name) {
+        None => Ok(None),
+        Some(index) => {
+            if index + 1 >= args.len() {
+                return Err(());
+            }
+            let value = args.remove(index + 1);
+            args.remove(index);
+            Ok(Some(value))
+        }
     }
+}
+
+//remove "--config [path]" and every "--db-opt [name]=[value]" from args, the overrides win over the file.
+//without --config ./token_poc.conf is read if it exists, else the built in defaults are used.
+fn take_config_args(args: &mut Vec<String>) -> Result<DbConfig, String> {
+    let mut config = match take_option_arg(args, "--config") {
+        Ok(Some(path)) => DbConfig::load(&path).map_err(|e| format!("{}", e))?,
+        Ok(None) => {
+            if Path::new(DEFAULT_CONFIG).exists() {
+                DbConfig::load(DEFAULT_CONFIG).map_err(|e| format!("{}", e))?
+            } else {
+                DbConfig::default()
+            }
+        },
+        Err(_) => return Err(String::from("--config needs a path")),
+    };
 
-    //increate balance array with balance_increased.
-    let mut balance: usize = bincode::serde::deserialize(&result[0..8]).unwrap();
-    balance += balance_increased;
-    let bytes = bincode::serde::serialize(&balance, bincode::SizeLimit::Infinite).unwrap();
-    result.splice(..8, bytes.iter().cloned());
-    result
+    loop {
+        let option = match take_option_arg(args, "--db-opt") {
+            Ok(Some(option)) => option,
+            Ok(None) => break,
+            Err(_) => return Err(String::from("--db-opt needs [name]=[value]")),
+        };
+        match option.find('=') {
+            Some(index) => config.set(option[..index].trim(), option[index + 1..].trim()).map_err(|e| format!("{}", e))?,
+            None => return Err(String::from("--db-opt needs [name]=[value]")),
+        }
+    }
+
+    //an override can clash with an option of the file, so the combination is checked once all are set.
+    config.validate().map_err(|e| format!("{}", e))?;
+    Ok(config)
 }
 
-fn get_db(dir: &str) -> DB {
+//parse comma separated token ids, for example: 7,8,42
+fn parse_token_ids(arg: &str) -> Option<Vec<usize>> {
+    let mut token_ids: Vec<usize> = Vec::new();
+    for id in arg.split(',') {
+        match id.trim().parse() {
+            Ok(n) => token_ids.push(n),
+            Err(_) => return None,
+        }
+    }
+    Some(token_ids)
+}
 
-    let mut opts = Options::default();
-    //let mut block_opts = BlockBasedOptions::default();
-    //double the block cach, 4096 as default.
-    //block_opts.set_block_size(4096);
-    //8MB as default, ext to 512 MB.
-    //block_opts.set_lru_cache(512*1024*1024);
-    //opts.set_compression_type(DBCompressionType::Lz4);
+//remove "--fee-flat [fee] --fee-per-token [fee] --fee-collector [address]" from args, either fee defaults to 0.
+fn take_fee_args(args: &mut Vec<String>) -> Result<Option<FeeSchedule>, &'static str> {
+    let flat = take_option_arg(args, "--fee-flat").map_err(|_| "--fee-flat needs a fee")?;
+    let per_token = take_option_arg(args, "--fee-per-token").map_err(|_| "--fee-per-token needs a fee")?;
+    let collector = take_option_arg(args, "--fee-collector").map_err(|_| "--fee-collector needs an address")?;
 
-    opts.increase_parallelism(8);
-    opts.set_max_background_flushes(6);
-    opts.set_max_background_compactions(6);
-    //opts.set_block_based_table_factory(&block_opts);
-    opts.create_if_missing(true);
+    let collector = match collector {
+        Some(collector) => collector,
+        None => {
+            if flat.is_some() || per_token.is_some() {
+                return Err("fees need a --fee-collector");
+            }
+            return Ok(None);
+        }
+    };
 
-    //default write buffer for cf is 64MB, ext it to 128MB;
-    opts.set_write_buffer_size(128 * 1024 * 1024);
-    opts.set_max_write_buffer_number(6);
-    //opts.set_disable_auto_compactions(true);
-    opts.set_bytes_per_sync(2*1024*1024);
+    let flat: usize = match flat {
+        Some(fee) => fee.parse().map_err(|_| "<fee> must be an number")?,
+        None => 0,
+    };
+    let per_token: usize = match per_token {
+        Some(fee) => fee.parse().map_err(|_| "<fee> must be an number")?,
+        None => 0,
+    };
+    Ok(Some(FeeSchedule { flat: flat, per_token: per_token, collector: collector }))
+}
 
-    opts.set_merge_operator("balance array merge handler", balance_array_merge_handler);
+//parse hex encoded bytes, for example a public key or a signature.
+fn parse_hex(arg: &str) -> Option<Vec<u8>> {
+    if arg.len() % 2 != 0 {
+        return None;
+    }
 
-    //to do better for configuable dir for db storage.
-    let db = DB::open(&opts, dir).unwrap();
-    db
+    let mut bytes: Vec<u8> = Vec::with_capacity(arg.len() / 2);
+    for i in (0..arg.len()).step_by(2) {
+        match arg.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()) {
+            Some(b) => bytes.push(b),
+            None => return None,
+        }
+    }
+    Some(bytes)
+}
+
+fn print_checkpoint(info: &CheckpointInfo) {
+    println!("checkpoint '{}': journal seq {}, created at {}, {} keys", info.name, info.seq, info.timestamp, info.keys);
+    for &(ref asset, total_supply) in &info.total_supply {
+        println!("    asset '{}' total supply: {}", asset, total_supply);
+    }
 }
 
 fn help() {
@@ -77,36 +171,216 @@ fn help() {
         "Usage Example:
     $ Init token id system by executing:
     $ > cargo run init-super [your_super_account_id] [total_supply]
+    $ optionally charge fees on end user transfers, paid to a collector account, by adding:
+    $ --fee-flat [fee] --fee-per-token [fee] --fee-collector [address]
+    $ Init refuses an initialized token id system, hand it with the remaining balance to a new super account by executing:
+    $ > cargo run rotate-super [your_super_account_id] [new_super_account_id]
+    $ Show the fees and how many tokens were collected by executing:
+    $ > cargo run fees
     $ Transfer tokens from one to another accounts by executing:
     $ > cargo run transfer [from] [to] [amount]
+    $ Transfer many legs at once, either all of them or none is applied, by executing:
+    $ > cargo run transfer-batch [from] [to] [amount] [from] [to] [amount] ...
+    $ Transfer specific token ids from one to another end user accounts by executing:
+    $ > cargo run transfer-tokens [from] [to] [token ids]
+    $ Allow a spender to take up to amount of tokens from an owner's account, 0 revokes it, by executing:
+    $ > cargo run approve [owner] [spender] [amount]
+    $ > cargo run allowance [owner] [spender]
+    $ Transfer approved tokens of an owner by its spender by executing:
+    $ > cargo run transfer-from [spender] [owner] [to] [amount]
+    $ Escrow tokens for a receiver until a deadline, cancel it before the deadline, or release the due ones by executing:
+    $ > cargo run escrow [from] [to] [amount] [deadline]
+    $ > cargo run cancel-escrow [from] [escrow id]
+    $ > cargo run settle-escrows
+    $ Mint new tokens to super account by executing:
+    $ > cargo run mint [your_super_account_id] [amount]
+    $ Burn tokens of an account by executing:
+    $ > cargo run burn [address] [amount]
+    $ > cargo run burn-tokens [address] [token ids]
+    $ Register the ed25519 public key of an account by super account, its transfers must be signed from then on, by executing:
+    $ > cargo run register-key [your_super_account_id] [address] [public key]
+    $ Freeze or unfreeze an end user account by super account, and list the frozen ones, by executing:
+    $ > cargo run freeze [your_super_account_id] [address]
+    $ > cargo run unfreeze [your_super_account_id] [address]
+    $ > cargo run frozen
+    $ Register an account with free form metadata, show it, and list the registered ones page by page, by executing:
+    $ > cargo run create-account [address] [metadata]
+    $ > cargo run account-info [address]
+    $ > cargo run list-accounts [limit] --prefix [account id prefix] --cursor [account id]
+    $ Reject transfers to accounts that are not registered, or accept them again, by super account by executing:
+    $ > cargo run require-registered [your_super_account_id] [on|off]
     $ Get balance of an anccount by executing:
     $ > cargo run balance [address]
+    $ Get balance of an account right after a journal sequence number, or at a unix timestamp, by executing:
+    $ > cargo run balance-at-seq [address] [seq]
+    $ > cargo run balance-at-time [address] [timestamp]
+    $ Get owner of a token id by executing:
+    $ > cargo run owner [token id]
+    $ List transactions of an account, oldest first, by executing:
+    $ > cargo run history [address] [cursor] [limit]
     $ Start bench mark by rolling out amount of tokens to amount of accounts by executing:
     $ > cargo run bench [I/O] [amount of account] [amount of tokens per account]
+    $ Check supply, token ids, balance headers and the owner index of the whole ledger, exits non-zero on any violation, by executing:
+    $ > cargo run audit
+    $ Count the keys of the meta, balances, journal and indexes families, or delete every key of one or all of them, by executing:
+    $ > cargo run families
+    $ > cargo run reset [meta | balances | journal | indexes | all]
+    $ Create a consistent checkpoint of the token storage under ./checkpoints, list them, or restore one to a new dir by executing:
+    $ > cargo run snapshot create [checkpoint name]
+    $ > cargo run snapshot list
+    $ > cargo run snapshot restore [checkpoint name] [dir]
+    $ Migrate a token storage created with an older key layout, and rebuild its owner index, by executing:
+    $ > cargo run migrate
+    $ Every command above except bench and migrate accepts an optional --asset [asset id] to pick the token system,
+    $ for example: cargo run balance --asset GOLD [address]
+    $ RocksDB options are read from the `name = value` lines of ./token_poc.conf, or of another file given by --config [path],
+    $ and any of them can be overridden by --db-opt [name]=[value], for example: --db-opt compression=lz4
+    $ transfer accepts an optional --tx-id [transaction id], a retry with the same id is not applied twice,
+    $ for example: cargo run transfer --tx-id order-42 [from] [to] [amount]
+    $ transfer from an account with a public key needs --nonce [nonce] --signature [signature],
+    $ for example: cargo run transfer --nonce 1 --signature 9f2c...0b [from] [to] [amount]
+    $ On startup an operation interrupted by a crash is finished or undone and the meta is checked,
+    $ only read commands are served if recovery finds a problem it cannot fix.
     $ Note: for better performance, please build binary in release mode.
 where:
     [your_super_account_id]\t\t Hash id of your account adrress, can be anything in string.
     [total_supply]\t\t\t The total supply of your tokens, for example: 50000000000
+    [fee]\t\t\t\t Number of tokens, --fee-flat is charged per transfer and --fee-per-token per token transfered.
     [from]\t\t\t\t Sender's address, for example super account's address.
     [to]\t\t\t\t Receiver's address, for any end user's account address.
     [amount]\t\t\t\t Number of tokens need to be transfered in the transaction.
+    [token ids]\t\t\t Comma separated token ids owned by the sender, for example: 7,8,42
     [address]\t\t\t\t Account address in string.
+    [owner]\t\t\t\t End user's account address whose tokens are spent.
+    [spender]\t\t\t\t Account address allowed to spend the owner's tokens.
+    [token id]\t\t\t\t A single token id, for example: 42
+    [cursor]\t\t\t\t Journal sequence number to start listing from, 0 for the first page, the next one is printed after each page.
+    [limit]\t\t\t\t Max number of transactions to list.
+    [deadline]\t\t\t\t Unix time in seconds the escrowed tokens are released to the receiver at.
+    [escrow id]\t\t\t Id printed when the escrow was created.
+    [checkpoint name]\t\t\t Letters, digits, '-' and '_', for example: before-upgrade
+    [dir]\t\t\t\t\t Path of a directory that does not exist yet or is empty.
+    [asset id]\t\t\t\t Id of an independent token system in the same storage, 'default' if not given.
+    [transaction id]\t\t\t Client chosen id of a transfer, unique per asset.
+    [public key]\t\t\t Hex encoded 32 bytes ed25519 public key.
+    [nonce]\t\t\t\t Number greater than the last nonce the sender used.
+    [signature]\t\t\t Hex encoded ed25519 signature of the bincode encoded (asset id, from, to, amount, nonce).
     [I/O]\t\t\t\t I | O | IO, command to write | read | write & read balance during the bench test."
     );
 }
 
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let asset = match take_asset_arg(&mut args) {
+        Ok(asset) => asset,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            help();
+            return;
+        }
+    };
+
+    let tx_id = match take_option_arg(&mut args, "--tx-id") {
+        Ok(tx_id) => tx_id,
+        Err(_) => {
+            eprintln!("error: --tx-id needs a transaction id");
+            help();
+            return;
+        }
+    };
+
+    //a signed transfer needs both of them.
+    let signed = match (take_option_arg(&mut args, "--nonce"), take_option_arg(&mut args, "--signature")) {
+        (Ok(None), Ok(None)) => None,
+        (Ok(Some(nonce)), Ok(Some(signature))) => {
+            match (nonce.parse(), parse_hex(&signature)) {
+                (Ok(nonce), Some(signature)) => Some(TransferAuth { nonce: nonce, signature: signature }),
+                _ => {
+                    eprintln!("error: <nonce> must be an number and <signature> hex encoded");
+                    help();
+                    return;
+                }
+            }
+        },
+        _ => {
+            eprintln!("error: a signed transfer needs both --nonce and --signature");
+            help();
+            return;
+        }
+    };
+
+    //fees are registered by init-super, a collector is needed to charge any.
+    let fees = match take_fee_args(&mut args) {
+        Ok(fees) => fees,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            help();
+            return;
+        }
+    };
+
+    //page of list-accounts, the listing starts at the first account when no cursor is given.
+    let (list_prefix, list_cursor) = match (take_option_arg(&mut args, "--prefix"), take_option_arg(&mut args, "--cursor")) {
+        (Ok(prefix), Ok(cursor)) => (prefix.unwrap_or(String::new()), cursor),
+        _ => {
+            eprintln!("error: --prefix and --cursor need an account id");
+            help();
+            return;
+        }
+    };
+
+    let config = match take_config_args(&mut args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            help();
+            return;
+        }
+    };
+    println!("RocksDB options:\n{}", config);
 
     let start = Instant::now();
-    let db = get_db("./token_storage");
-    let mut bench_db = get_db("./bench_token_storage");
+    let db = get_db("./token_storage", &config);
+    let bench_db = get_db("./bench_token_storage", &config);
     
     let elapsed = start.elapsed();
     println!("On starup, get db loaded. It cost: {} ms",
              (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
 
+    //takes any number of legs, so it does not fit the dispatch on the number of args below.
+    if args.len() > 1 && args[1] == "transfer-batch" {
+        if args.len() < 5 || (args.len() - 2) % 3 != 0 {
+            eprintln!("error: transfer-batch needs [from] [to] [amount] for every leg");
+            help();
+            return;
+        }
+
+        let mut legs: Vec<(String, String, usize)> = Vec::new();
+        for leg in args[2..].chunks(3) {
+            match leg[2].parse() {
+                Ok(amount) => legs.push((leg[0].clone(), leg[1].clone(), amount)),
+                Err(_) => {
+                    eprintln!("error: <amount> must be an number");
+                    help();
+                    return;
+                }
+            }
+        }
+
+        let start = Instant::now();
+        match transfer_batch(&db, &asset, &legs) {
+            Ok(()) => {
+                println!("Transfer done!");
+                let elapsed = start.elapsed();
+                println!("Transfer cost: {} ms",
+                        (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+            },
+            Err(e) => eprintln!("Got error: {}", e),
+        }
+        return;
+    }
+
     match args.len() {
         1 => {
             // no args
@@ -114,7 +388,106 @@ fn main() {
         }
         2 => {
             // one arg passed
-            help();
+            let command = &args[1];
+
+            match &command[..] {
+                "migrate" => {
+                    let start = Instant::now();
+                    match migrate_legacy_keys(&db) {
+                        Ok(moved) => {
+                            println!("{} keys moved, token_storage is using the namespaced key layout.", moved);
+                            let elapsed = start.elapsed();
+                            println!("migrate cost: {} ms",
+                                    (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "families" => {
+                    match family_sizes(&db) {
+                        Ok(sizes) => {
+                            for (family, size) in sizes {
+                                println!("{}: {} keys", family.name(), size);
+                            }
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "settle-escrows" => {
+                    let start = Instant::now();
+                    match settle_escrows(&db, &asset) {
+                        Ok(settled) => {
+                            println!("{} escrows settled.", settled);
+                            let elapsed = start.elapsed();
+                            println!("settle-escrows cost: {} ms",
+                                    (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                //exits non-zero on any violation, so scripts can alert on it.
+                "audit" => {
+                    let start = Instant::now();
+                    match audit(&db, &asset) {
+                        Ok(violations) => {
+                            for violation in &violations {
+                                println!("violation: {}", violation);
+                            }
+                            let elapsed = start.elapsed();
+                            println!("audit cost: {} ms",
+                                    (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                            if violations.len() > 0 {
+                                eprintln!("audit failed with {} violations.", violations.len());
+                                process::exit(1);
+                            }
+                            println!("audit passed.");
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            process::exit(1);
+                        },
+                    }
+                }
+                "fees" => {
+                    match (fee_schedule(&db, &asset), fees_collected(&db, &asset)) {
+                        (Ok(Some(fees)), Ok(collected)) => {
+                            println!("flat fee: {}, fee per token: {}, collector: '{}'", fees.flat, fees.per_token, fees.collector);
+                            println!("{} tokens collected as fees.", collected);
+                        },
+                        (Ok(None), _) => println!("no fee is charged."),
+                        (Err(e), _) | (_, Err(e)) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "frozen" => {
+                    match frozen_accounts(&db, &asset) {
+                        Ok(accounts) => {
+                            for account in &accounts {
+                                println!("{}", account);
+                            }
+                            println!("{} accounts are frozen.", accounts.len());
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                _ => {
+                    help();
+                }
+            }
         }
         3 => {
             // two args passed
@@ -125,7 +498,7 @@ fn main() {
             match &command[..] {
                 "balance" => {
                     let start = Instant::now();
-                    match get_balance(&db, &address) {
+                    match get_balance(&db, &asset, &address) {
                         Ok(value) => {
                             println!("{} have {} tokens", address, value);
                             let elapsed = start.elapsed();
@@ -133,7 +506,105 @@ fn main() {
                                     (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
                         },
                         Err(e) => {
-                            println!("Got error: {:?}", e);
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "owner" => {
+                    let token_id: usize = match address.parse() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            eprintln!("error: <token id> must be an number");
+                            help();
+                            return;
+                        }
+                    };
+                    let start = Instant::now();
+                    match owner_of(&db, &asset, token_id) {
+                        Ok(Some(owner)) => println!("token {} is owned by '{}'", token_id, owner),
+                        Ok(None) => println!("token {} has not been issued", token_id),
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                    let elapsed = start.elapsed();
+                    println!("owner_of cost: {} ms",
+                            (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                }
+                //"all" resets every family, the token storage is empty afterwards.
+                "reset" => {
+                    let start = Instant::now();
+                    let result = match Family::from_name(&address) {
+                        Some(family) => reset_family(&db, family),
+                        None if address == "all" => reset_ledger(&db),
+                        None => {
+                            eprintln!("error: <family> must be meta, balances, journal, indexes or all");
+                            help();
+                            return;
+                        }
+                    };
+                    match result {
+                        Ok(deleted) => {
+                            println!("{} keys deleted.", deleted);
+                            let elapsed = start.elapsed();
+                            println!("reset cost: {} ms",
+                                    (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "account-info" => {
+                    match account_info(&db, &asset, &address) {
+                        Ok(info) => println!("'{}' is registered at {} with metadata: {}", info.id, info.created, info.metadata),
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "list-accounts" => {
+                    let limit: usize = match address.parse() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            eprintln!("error: <limit> must be an number");
+                            help();
+                            return;
+                        }
+                    };
+                    let start = Instant::now();
+                    match list_accounts(&db, &asset, &list_prefix, list_cursor.as_ref(), limit) {
+                        Ok((accounts, next)) => {
+                            for info in accounts {
+                                println!("'{}' registered at {} with metadata: {}", info.id, info.created, info.metadata);
+                            }
+                            match next {
+                                Some(cursor) => println!("next cursor: {}", cursor),
+                                None => println!("no more accounts."),
+                            }
+                            let elapsed = start.elapsed();
+                            println!("list_accounts cost: {} ms",
+                                    (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "snapshot" if address == "list" => {
+                    match list_checkpoints(CHECKPOINT_ROOT) {
+                        Ok(checkpoints) => {
+                            for info in checkpoints {
+                                print_checkpoint(&info);
+                            }
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
                             return;
                         },
                     }
@@ -146,23 +617,23 @@ fn main() {
         }
         4 => {
             //three args passed
-            //to do the init super account.
             let command = &args[1];
-            let super_account_id = &args[2];
-            let total = &args[3];
-            let total_supply: usize = match total.parse() {
-	            Ok(n) => n,
-                Err(_) => {
-                    eprintln!("error: <total supply> must be an number");
-                    help();
-                    return;
-                }
-            };
 
             match &command[..] {
+                //to do the init super account.
                 "init-super" => {
+                    let super_account_id = &args[2];
+                    let total = &args[3];
+                    let total_supply: usize = match total.parse() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            eprintln!("error: <total supply> must be an number");
+                            help();
+                            return;
+                        }
+                    };
                     let start = Instant::now();
-                    match init_super_account(&db, &super_account_id, total_supply){
+                    match init_super_account(&db, &asset, &super_account_id, total_supply, fees.as_ref()){
                         Ok(()) => {
                             println!("Token id system is ready to use.");
                             let elapsed = start.elapsed();
@@ -170,7 +641,196 @@ fn main() {
                                     (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
                         },
                         Err(e) => {
-                            println!("Got error: {:?}", e);
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "mint" | "burn" => {
+                    let account = &args[2];
+                    let amount: usize = match args[3].parse() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            eprintln!("error: <amount> must be an number");
+                            help();
+                            return;
+                        }
+                    };
+                    let start = Instant::now();
+                    let result = if command == "mint" {
+                        mint(&db, &asset, &account, amount)
+                    } else {
+                        burn(&db, &asset, &account, amount)
+                    };
+                    match result {
+                        Ok(()) => {
+                            println!("{} done!", command);
+                            let elapsed = start.elapsed();
+                            println!("{} cost: {} ms", command,
+                                    (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "rotate-super" => {
+                    let current = &args[2];
+                    let new_id = &args[3];
+                    let start = Instant::now();
+                    match rotate_super_account(&db, &asset, &current, &new_id) {
+                        Ok(()) => {
+                            let elapsed = start.elapsed();
+                            println!("rotate_super_account cost: {} ms",
+                                    (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "freeze" | "unfreeze" => {
+                    let admin = &args[2];
+                    let account = &args[3];
+                    let result = if command == "freeze" {
+                        freeze(&db, &asset, &admin, &account)
+                    } else {
+                        unfreeze(&db, &asset, &admin, &account)
+                    };
+                    match result {
+                        Ok(()) => println!("{} done!", command),
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "cancel-escrow" => {
+                    let account = &args[2];
+                    let id: usize = match args[3].parse() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            eprintln!("error: <escrow id> must be an number");
+                            help();
+                            return;
+                        }
+                    };
+                    match cancel_escrow(&db, &asset, &account, id) {
+                        Ok(()) => println!("cancel-escrow done!"),
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "balance-at-seq" | "balance-at-time" => {
+                    let account = &args[2];
+                    let point: u64 = match args[3].parse() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            eprintln!("error: <seq> or <timestamp> must be an number");
+                            help();
+                            return;
+                        }
+                    };
+                    let at = if command == "balance-at-seq" {
+                        JournalPoint::Seq(point as usize)
+                    } else {
+                        JournalPoint::Timestamp(point)
+                    };
+                    let start = Instant::now();
+                    match get_balance_at(&db, &asset, &account, at) {
+                        Ok(value) => {
+                            println!("{} had {} tokens at {}", account, value, point);
+                            let elapsed = start.elapsed();
+                            println!("get_balance_at cost: {} ms",
+                                    (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "allowance" => {
+                    let owner = &args[2];
+                    let spender = &args[3];
+                    match allowance(&db, &asset, &owner, &spender) {
+                        Ok(value) => println!("'{}' is allowed to take {} tokens of '{}'", spender, value, owner),
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "create-account" => {
+                    let account = &args[2];
+                    let metadata = &args[3];
+                    match create_account(&db, &asset, &account, &metadata) {
+                        Ok(()) => println!("create-account done!"),
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "require-registered" => {
+                    let admin = &args[2];
+                    let required = match &args[3][..] {
+                        "on" => true,
+                        "off" => false,
+                        _ => {
+                            eprintln!("error: <on|off> must be on or off");
+                            help();
+                            return;
+                        }
+                    };
+                    match require_registered(&db, &asset, &admin, required) {
+                        Ok(()) => println!("require-registered done!"),
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "burn-tokens" => {
+                    let account = &args[2];
+                    let token_ids = match parse_token_ids(&args[3]) {
+                        Some(ids) => ids,
+                        None => {
+                            eprintln!("error: <token ids> must be comma separated numbers");
+                            help();
+                            return;
+                        }
+                    };
+                    let start = Instant::now();
+                    match burn_tokens(&db, &asset, &account, &token_ids) {
+                        Ok(()) => {
+                            println!("burn done!");
+                            let elapsed = start.elapsed();
+                            println!("burn cost: {} ms",
+                                    (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "snapshot" if args[2] == "create" => {
+                    let name = &args[3];
+                    let start = Instant::now();
+                    match create_checkpoint(&db, CHECKPOINT_ROOT, name) {
+                        Ok(info) => {
+                            print_checkpoint(&info);
+                            let elapsed = start.elapsed();
+                            println!("snapshot {} cost: {} ms", "create",
+                                    (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
                             return;
                         },
                     }
@@ -185,6 +845,25 @@ fn main() {
             //four args passed
             let command = &args[1];
             match &command[..] {
+                "register-key" => {
+                    let admin = &args[2];
+                    let account = &args[3];
+                    let public_key = match parse_hex(&args[4]) {
+                        Some(public_key) => public_key,
+                        None => {
+                            eprintln!("error: <public key> must be hex encoded");
+                            help();
+                            return;
+                        }
+                    };
+                    match register_public_key(&db, &asset, &admin, &account, &public_key) {
+                        Ok(()) => println!("'{}' must sign its transfers from now on.", account),
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
                 //to do the transfer.
                 "transfer" => {
                     let from = &args[2];
@@ -199,7 +878,32 @@ fn main() {
                         }
                     };
                     let start = Instant::now();              
-                    match transfer(&db, &from, &to, num_of_tokens) {
+                    match transfer(&db, &asset, &from, &to, num_of_tokens, tx_id.as_ref(), signed.as_ref()) {
+                        Ok(()) => {
+                            println!("Transfer done!");
+                            let elapsed = start.elapsed();
+                            println!("Transfer cost: {} ms",
+                                    (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "transfer-tokens" => {
+                    let from = &args[2];
+                    let to = &args[3];
+                    let token_ids = match parse_token_ids(&args[4]) {
+                        Some(ids) => ids,
+                        None => {
+                            eprintln!("error: <token ids> must be comma separated numbers");
+                            help();
+                            return;
+                        }
+                    };
+                    let start = Instant::now();
+                    match transfer_tokens(&db, &asset, &from, &to, &token_ids) {
                         Ok(()) => {
                             println!("Transfer done!");
                             let elapsed = start.elapsed();
@@ -207,7 +911,65 @@ fn main() {
                                     (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
                         },
                         Err(e) => {
-                            println!("Got error: {:?}", e);
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "approve" => {
+                    let owner = &args[2];
+                    let spender = &args[3];
+                    let amount: usize = match args[4].parse() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            eprintln!("error: <amount> must be an number");
+                            help();
+                            return;
+                        }
+                    };
+                    match approve(&db, &asset, &owner, &spender, amount) {
+                        Ok(()) => println!("Approve done!"),
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "history" => {
+                    let address = &args[2];
+                    let cursor: usize = match args[3].parse() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            eprintln!("error: <cursor> must be an number");
+                            help();
+                            return;
+                        }
+                    };
+                    let limit: usize = match args[4].parse() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            eprintln!("error: <limit> must be an number");
+                            help();
+                            return;
+                        }
+                    };
+                    let start = Instant::now();
+                    match history(&db, &asset, &address, cursor, limit) {
+                        Ok((records, next)) => {
+                            for record in records {
+                                println!("#{} at {} {} '{}' -> '{}' {} tokens {:?}", record.seq, record.timestamp,
+                                        record.kind.name(), record.from, record.to, record.amount, record.tokens);
+                            }
+                            match next {
+                                Some(cursor) => println!("next cursor: {}", cursor),
+                                None => println!("no more transactions."),
+                            }
+                            let elapsed = start.elapsed();
+                            println!("history cost: {} ms",
+                                    (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
                             return;
                         },
                     }
@@ -234,10 +996,91 @@ fn main() {
                         }
                     };
 
-                    match bench_test(&mut bench_db, &io, num_of_tokens, num_of_accounts) {
+                    match bench_test(&bench_db, &io, num_of_tokens, num_of_accounts) {
                         Ok(()) => println!("Bench test done!"),
                         Err(e) => {
-                            println!("Got error: {:?}", e);
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                //the restored db is a new directory, stop the ledger and move it in place of token_storage to use it.
+                "snapshot" if args[2] == "restore" => {
+                    let name = &args[3];
+                    let target = &args[4];
+                    let start = Instant::now();
+                    match restore_checkpoint(CHECKPOINT_ROOT, name, target) {
+                        Ok(restored) => {
+                            println!("{} keys restored to {}.", restored, target);
+                            let elapsed = start.elapsed();
+                            println!("snapshot {} cost: {} ms", "restore",
+                                    (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                _ => {
+                    eprintln!("error: invalid command.");
+                    help();
+                }
+            }
+        }
+        6 => {
+            //five args passed
+            let command = &args[1];
+            match &command[..] {
+                "escrow" => {
+                    let from = &args[2];
+                    let to = &args[3];
+                    let num_of_tokens: usize = match args[4].parse() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            eprintln!("error: <amount> must be an number");
+                            help();
+                            return;
+                        }
+                    };
+                    let deadline: u64 = match args[5].parse() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            eprintln!("error: <deadline> must be an number");
+                            help();
+                            return;
+                        }
+                    };
+                    match create_escrow(&db, &asset, &from, &to, num_of_tokens, deadline) {
+                        Ok(id) => println!("Escrow #{} created!", id),
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
+                            return;
+                        },
+                    }
+                }
+                "transfer-from" => {
+                    let spender = &args[2];
+                    let owner = &args[3];
+                    let to = &args[4];
+                    let num_of_tokens: usize = match args[5].parse() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            eprintln!("error: <amount> must be an number");
+                            help();
+                            return;
+                        }
+                    };
+                    let start = Instant::now();
+                    match transfer_from(&db, &asset, &spender, &owner, &to, num_of_tokens) {
+                        Ok(()) => {
+                            println!("Transfer done!");
+                            let elapsed = start.elapsed();
+                            println!("Transfer cost: {} ms",
+                                    (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64);
+                        },
+                        Err(e) => {
+                            eprintln!("Got error: {}", e);
                             return;
                         },
                     }
@@ -0,0 +1,128 @@
+extern crate exonum_rocksdb;
+extern crate bincode;
+
+use std::cmp;
+use std::result::Result;
+use self::exonum_rocksdb::WriteBatch;
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::{self, owner_index_key};
+use super::token_set::TokenSet;
+
+//token id -> owner reverse index. one entry per owned range [start, end), keyed by end and holding (start, owner),
+//so the first entry at or after id + 1 is the only one that can contain id.
+//ids still held by super account have no entry.
+
+//owner of token_id according to the index.
+pub fn lookup(db: &Ledger, asset: &String, token_id: usize) -> Result<Option<String>, LedgerError> {
+    let next = match token_id.checked_add(1) {
+        Some(next) => next,
+        None => return Err(LedgerError::InvalidInput("token id is too large")),
+    };
+    let mut iter = db.raw_iterator();
+    iter.seek(&owner_index_key(asset, next));
+    if !iter.valid() {
+        return Ok(None);
+    }
+
+    let key = iter.key().unwrap();
+    if !key.starts_with(&keys::asset_prefix(keys::OWNER_INDEX_PREFIX, asset)) {
+        return Ok(None);
+    }
+
+    let (start, owner) = decode_entry(&iter.value().unwrap())?;
+    if start <= token_id {
+        return Ok(Some(owner));
+    }
+    Ok(None)
+}
+
+//index a freshly rolled out block, those ids never had an owner other than super account.
+pub fn index_rollout(batch: &mut WriteBatch, asset: &String, start: usize, end: usize, to: &String) -> Result<(), LedgerError> {
+    put_entry(batch, asset, start, end, to)
+}
+
+//re-point tx_tokens from sender to receiver, caller must hold the sender's lock.
+pub fn index_transfer(db: &Ledger, batch: &mut WriteBatch, asset: &String, from: &String, to: &String, tx_tokens: &TokenSet) -> Result<(), LedgerError> {
+    unindex(db, batch, asset, from, tx_tokens)?;
+
+    for &(start, end) in tx_tokens.ranges() {
+        put_entry(batch, asset, start, end, to)?;
+    }
+    Ok(())
+}
+
+//drop tokens from the index, e.g. when they are burned. entries overlapping a removed range are owned by `from`,
+//they get deleted and whatever part of them is not removed is written back for `from`.
+//caller must hold the lock of `from`.
+pub fn unindex(db: &Ledger, batch: &mut WriteBatch, asset: &String, from: &String, tx_tokens: &TokenSet) -> Result<(), LedgerError> {
+    let prefix = keys::asset_prefix(keys::OWNER_INDEX_PREFIX, asset);
+    let mut touched: Vec<(Vec<u8>, usize, usize)> = Vec::new();
+
+    for &(start, end) in tx_tokens.ranges() {
+        let mut iter = db.raw_iterator();
+        iter.seek(&owner_index_key(asset, start + 1));
+        while iter.valid() {
+            let key = iter.key().unwrap();
+            if !key.starts_with(&prefix) {
+                break;
+            }
+
+            let (entry_start, _) = decode_entry(&iter.value().unwrap())?;
+            if entry_start >= end {
+                break;
+            }
+
+            let entry_end = entry_end_of(&key[prefix.len()..])?;
+            if !touched.iter().any(|t| t.0 == key) {
+                touched.push((key, entry_start, entry_end));
+            }
+            iter.next();
+        }
+    }
+
+    for (key, entry_start, entry_end) in touched {
+        batch.delete(&key)?;
+
+        let mut rest = TokenSet::from_range(entry_start, entry_end);
+        for &(start, end) in tx_tokens.ranges() {
+            let overlap_start = cmp::max(start, entry_start);
+            let overlap_end = cmp::min(end, entry_end);
+            rest.remove_range(overlap_start, overlap_end);
+        }
+
+        for &(start, end) in rest.ranges() {
+            put_entry(batch, asset, start, end, from)?;
+        }
+    }
+    Ok(())
+}
+
+//point tokens that had no index entry left, e.g. after unindex, to owner.
+pub fn index_owned(batch: &mut WriteBatch, asset: &String, tokens: &TokenSet, owner: &String) -> Result<(), LedgerError> {
+    for &(start, end) in tokens.ranges() {
+        put_entry(batch, asset, start, end, owner)?;
+    }
+    Ok(())
+}
+
+fn put_entry(batch: &mut WriteBatch, asset: &String, start: usize, end: usize, owner: &String) -> Result<(), LedgerError> {
+    let value = bincode::serde::serialize(&(start, owner), bincode::SizeLimit::Infinite).unwrap();
+    batch.put(&owner_index_key(asset, end), &value)?;
+    Ok(())
+}
+
+fn decode_entry(bytes: &[u8]) -> Result<(usize, String), LedgerError> {
+    match bincode::serde::deserialize(bytes) {
+        Ok(entry) => Ok(entry),
+        Err(_) => Err(LedgerError::Codec("owner index entry")),
+    }
+}
+
+//decode the big-endian end id, the key has its asset prefix stripped already.
+fn entry_end_of(id: &[u8]) -> Result<usize, LedgerError> {
+    match keys::from_be_bytes(id) {
+        Some(end) => Ok(end),
+        None => Err(LedgerError::Codec("owner index key")),
+    }
+}
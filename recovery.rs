@@ -0,0 +1,242 @@
+extern crate bincode;
+
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::result::Result;
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::{self, meta_key, ledger_meta_key, journal_key};
+use super::lock::{lock_keys, KeyLocks};
+use super::family;
+use super::fee;
+
+//startup recovery of a token storage, run when the ledger is opened and before any write is served:
+//  an operation made of more than one write records its intent first and deletes it with its last write,
+//  an intent found on startup was interrupted and is rolled forward or back.
+//  ledger and asset meta must be present and decodable.
+//  a quick supply check: SuperBalance never exceeds TotalSupply. the full check of the balances is audit.rs.
+//every other operation is a single atomic batch and needs no recovery.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Intent {
+    //reset_ledger deletes the families in one batch each, rolled forward by deleting the rest of them.
+    ResetLedger,
+    //create_checkpoint copies the ledger into the dir and writes its metadata last,
+    //rolled back by removing the dir unless the metadata made it to disk.
+    CreateCheckpoint(String),
+}
+
+impl Intent {
+    fn to_bytes(&self) -> Vec<u8> {
+        let value = match *self {
+            Intent::ResetLedger => (1u8, ""),
+            Intent::CreateCheckpoint(ref dir) => (2u8, &dir[..]),
+        };
+        bincode::serde::serialize(&value, bincode::SizeLimit::Infinite).unwrap()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Intent, LedgerError> {
+        let value: (u8, String) = match bincode::serde::deserialize(bytes) {
+            Ok(value) => value,
+            Err(_) => return Err(LedgerError::Codec("intent record")),
+        };
+        match value.0 {
+            1 => Ok(Intent::ResetLedger),
+            2 => Ok(Intent::CreateCheckpoint(value.1)),
+            _ => Err(LedgerError::Codec("intent kind")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryReport {
+    //what recovery changed, e.g. an interrupted operation rolled forward.
+    pub actions: Vec<String>,
+    //what recovery could not fix, writes must not be served while there is any.
+    pub problems: Vec<String>,
+}
+
+impl RecoveryReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.len() == 0
+    }
+}
+
+impl fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for action in &self.actions {
+            writeln!(f, "recovered: {}", action)?;
+        }
+        for problem in &self.problems {
+            writeln!(f, "problem: {}", problem)?;
+        }
+        write!(f, "{} actions taken, {} problems left.", self.actions.len(), self.problems.len())
+    }
+}
+
+//run by open_ledger, an Err means the storage could not be read, which refuses writes the same as a problem in the report.
+pub fn recover(db: &Ledger) -> Result<RecoveryReport, LedgerError> {
+    let mut report = RecoveryReport::default();
+
+    match db.get(&ledger_meta_key(keys::INTENT))? {
+        Some(bytes) => {
+            match Intent::from_bytes(&bytes) {
+                Ok(intent) => resolve_intent(db, &intent, &mut report)?,
+                Err(e) => report.problems.push(format!("{}: {}", meta_name(&ledger_meta_key(keys::INTENT)), e)),
+            }
+        },
+        None => {},
+    }
+
+    check_ledger_meta(db, &mut report)?;
+    for asset in assets(db)? {
+        check_asset_meta(db, &asset, &mut report)?;
+    }
+
+    Ok(report)
+}
+
+//record the intent of a multi-step operation, one can be in progress at a time.
+//an interrupted one was resolved when the ledger was opened, so a pending intent belongs to a concurrent call.
+//the intent is deleted by end_intent, or in the last batch of the operation if that batch already deletes it.
+pub(crate) fn begin_intent(db: &Ledger, intent: &Intent) -> Result<KeyLocks, LedgerError> {
+    let locks = lock_keys(db, vec![ledger_meta_key(keys::INTENT)])?;
+    if db.get(&ledger_meta_key(keys::INTENT))?.is_some() {
+        return Err(LedgerError::Conflict(meta_name(&ledger_meta_key(keys::INTENT))));
+    }
+
+    db.put(&ledger_meta_key(keys::INTENT), &intent.to_bytes())?;
+    Ok(locks)
+}
+
+pub(crate) fn end_intent(db: &Ledger) -> Result<(), LedgerError> {
+    db.delete(&ledger_meta_key(keys::INTENT))?;
+    Ok(())
+}
+
+fn resolve_intent(db: &Ledger, intent: &Intent, report: &mut RecoveryReport) -> Result<(), LedgerError> {
+    match *intent {
+        Intent::ResetLedger => {
+            //deleting a key twice is harmless, so every family is reset again and meta, holding the intent, last.
+            let mut deleted: usize = 0;
+            for family in family::FAMILIES.iter().rev() {
+                deleted += family::reset_family(db, *family)?;
+            }
+            report.actions.push(format!("interrupted ledger reset rolled forward, {} keys deleted", deleted));
+            Ok(())
+        },
+        Intent::CreateCheckpoint(ref dir) => {
+            if Path::new(&format!("{}.meta", dir)).exists() {
+                report.actions.push(format!("checkpoint {} was complete, its intent is cleared", dir));
+            } else {
+                match fs::remove_dir_all(dir) {
+                    Ok(()) => report.actions.push(format!("incomplete checkpoint {} rolled back", dir)),
+                    Err(ref e) if e.kind() == ErrorKind::NotFound => {
+                        report.actions.push(format!("incomplete checkpoint {} had nothing on disk", dir));
+                    },
+                    Err(e) => {
+                        report.problems.push(format!("incomplete checkpoint {} cannot be removed: {}", dir, e));
+                        return Ok(());
+                    },
+                }
+            }
+            end_intent(db)
+        },
+    }
+}
+
+fn check_ledger_meta(db: &Ledger, report: &mut RecoveryReport) -> Result<(), LedgerError> {
+    match read_usize(db, &ledger_meta_key(keys::SCHEMA_VERSION), report)? {
+        Some(version) if version > keys::CURRENT_SCHEMA_VERSION => {
+            report.problems.push(format!("schema version {} is newer than {} this build knows", version, keys::CURRENT_SCHEMA_VERSION));
+        },
+        _ => {},
+    }
+
+    //the sequence number is bumped in the batch of every record, a record at it means the two got apart.
+    if let Some(seq) = read_usize(db, &ledger_meta_key(keys::JOURNAL_SEQ), report)? {
+        if db.get(&journal_key(seq))?.is_some() {
+            report.problems.push(format!("journal record #{} exists at the next sequence number", seq));
+        }
+    }
+    Ok(())
+}
+
+fn check_asset_meta(db: &Ledger, asset: &String, report: &mut RecoveryReport) -> Result<(), LedgerError> {
+    match db.get(&meta_key(asset, keys::SUPER_ACCOUNT))? {
+        Some(ref value) if value.len() > 0 && ::std::str::from_utf8(value).is_ok() => {},
+        _ => report.problems.push(format!("{}: not an account id", meta_name(&meta_key(asset, keys::SUPER_ACCOUNT)))),
+    }
+
+    let total_supply = read_usize(db, &meta_key(asset, keys::TOTAL_SUPPLY), report)?;
+    let super_balance = read_usize(db, &meta_key(asset, keys::SUPER_BALANCE), report)?;
+    for name in [keys::BURNED, keys::FEES_COLLECTED, keys::ESCROW_SEQ].iter() {
+        read_usize(db, &meta_key(asset, name), report)?;
+    }
+    if let Err(e) = fee::fee_schedule(db, asset) {
+        match e {
+            LedgerError::Codec(_) => report.problems.push(format!("{}: {}", meta_name(&meta_key(asset, keys::FEE_SCHEDULE)), e)),
+            e => return Err(e),
+        }
+    }
+
+    match (total_supply, super_balance) {
+        (Some(total_supply), Some(super_balance)) => {
+            if super_balance > total_supply {
+                report.problems.push(format!("asset '{}': SuperBalance {} exceeds TotalSupply {}", asset, super_balance, total_supply));
+            }
+        },
+        _ => {
+            //undecodable ones are reported already.
+            for name in [keys::TOTAL_SUPPLY, keys::SUPER_BALANCE].iter() {
+                if db.get(&meta_key(asset, name))?.is_none() {
+                    report.problems.push(format!("{}: missing", meta_name(&meta_key(asset, name))));
+                }
+            }
+        },
+    }
+    Ok(())
+}
+
+//every initialized asset, found by its m:<asset>:SuperAccount key.
+fn assets(db: &Ledger) -> Result<Vec<String>, LedgerError> {
+    let suffix = [&[keys::SEPARATOR][..], keys::SUPER_ACCOUNT.as_bytes()].concat();
+    let mut assets: Vec<String> = Vec::new();
+    let mut iter = db.raw_iterator();
+    iter.seek(keys::META_PREFIX);
+    while iter.valid() {
+        let key = iter.key().unwrap();
+        if !key.starts_with(keys::META_PREFIX) {
+            break;
+        }
+
+        let name = &key[keys::META_PREFIX.len()..];
+        if name.ends_with(&suffix) {
+            assets.push(String::from_utf8_lossy(&name[..name.len() - suffix.len()]).into_owned());
+        }
+        iter.next();
+    }
+    Ok(assets)
+}
+
+//a value that does not decode is a problem, a missing one is left to the caller.
+fn read_usize(db: &Ledger, key: &[u8], report: &mut RecoveryReport) -> Result<Option<usize>, LedgerError> {
+    match db.get(key)? {
+        Some(bytes) => {
+            match bincode::serde::deserialize(&bytes) {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => {
+                    report.problems.push(format!("{}: not a number", meta_name(key)));
+                    Ok(None)
+                },
+            }
+        },
+        None => Ok(None),
+    }
+}
+
+fn meta_name(key: &[u8]) -> String {
+    String::from_utf8_lossy(key).into_owned()
+}
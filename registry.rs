@@ -0,0 +1,128 @@
+extern crate bincode;
+
+use std::result::Result;
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::{self, meta_key, registry_key};
+use super::lock::lock_keys;
+use super::journal;
+use super::account::get_super_account_id;
+use super::auth;
+
+//explicit registry of the accounts of an asset. balances still come into existence on the first merge,
+//registering an account only records it, so accounts that hold tokens from before can be registered later.
+//super account can make transfers to accounts that are not registered fail.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountInfo {
+    pub id: String,
+    //seconds since unix epoch.
+    pub created: u64,
+    //free form, e.g. a name or a json document of the client.
+    pub metadata: String,
+}
+
+pub fn create_account(db: &Ledger, asset: &String, account: &String, metadata: &String) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
+
+    if account.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty account id"));
+    }
+
+    let _locks = lock_keys(db, vec![registry_key(asset, account)])?;
+    if db.get(&registry_key(asset, account))?.is_some() {
+        return Err(LedgerError::InvalidInput("account is registered already"));
+    }
+
+    let value = bincode::serde::serialize(&(journal::now_secs(), metadata), bincode::SizeLimit::Infinite).unwrap();
+    db.put(&registry_key(asset, account), &value)?;
+
+    println!("Account '{}' is registered.", account);
+    Ok(())
+}
+
+pub fn account_info(db: &Ledger, asset: &String, account: &String) -> Result<AccountInfo, LedgerError> {
+    keys::check_asset(asset)?;
+
+    match db.get(&registry_key(asset, account))? {
+        Some(bytes) => decode_info(account.clone(), &bytes),
+        None => Err(LedgerError::AccountNotFound(account.clone())),
+    }
+}
+
+//registered accounts whose id starts with prefix, in id order, starting at id cursor.
+//returns at most limit accounts and the cursor of the next page, None if there is no more.
+pub fn list_accounts(db: &Ledger, asset: &String, prefix: &String, cursor: Option<&String>, limit: usize) -> Result<(Vec<AccountInfo>, Option<String>), LedgerError> {
+    keys::check_asset(asset)?;
+
+    if limit == 0 {
+        return Err(LedgerError::InvalidInput("limit must be greater than zero"));
+    }
+
+    let key_prefix = registry_key(asset, prefix);
+    let id_start = keys::asset_prefix(keys::REGISTRY_PREFIX, asset).len();
+    let mut accounts: Vec<AccountInfo> = Vec::new();
+    let mut iter = db.raw_iterator();
+    match cursor {
+        Some(cursor) if cursor > prefix => iter.seek(&registry_key(asset, cursor)),
+        _ => iter.seek(&key_prefix),
+    }
+    while iter.valid() {
+        let key = iter.key().unwrap();
+        if !key.starts_with(&key_prefix) {
+            break;
+        }
+
+        let id = match String::from_utf8(key[id_start..].to_vec()) {
+            Ok(id) => id,
+            Err(_) => return Err(LedgerError::Codec("registry key")),
+        };
+
+        if accounts.len() == limit {
+            return Ok((accounts, Some(id)));
+        }
+
+        accounts.push(decode_info(id, &iter.value().unwrap())?);
+        iter.next();
+    }
+    Ok((accounts, None))
+}
+
+//turn on or off the rejection of transfers to accounts that are not registered, super account only.
+pub fn require_registered(db: &Ledger, asset: &String, admin: &String, required: bool) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
+
+    if get_super_account_id(db, asset)? != *admin {
+        return Err(LedgerError::PermissionDenied(admin.clone()));
+    }
+    auth::check_unsigned(db, asset, admin)?;
+
+    if required {
+        db.put(&meta_key(asset, keys::REQUIRE_REGISTERED), &[])?;
+        println!("Transfers to accounts that are not registered are rejected from now on.");
+    } else {
+        db.delete(&meta_key(asset, keys::REQUIRE_REGISTERED))?;
+        println!("Transfers to any account are accepted from now on.");
+    }
+    Ok(())
+}
+
+//fails with AccountNotFound if the asset requires registered receivers and account is not one of them.
+pub(crate) fn check_registered(db: &Ledger, asset: &String, account: &String) -> Result<(), LedgerError> {
+    if db.get(&meta_key(asset, keys::REQUIRE_REGISTERED))?.is_none() {
+        return Ok(());
+    }
+
+    if db.get(&registry_key(asset, account))?.is_none() {
+        return Err(LedgerError::AccountNotFound(account.clone()));
+    }
+    Ok(())
+}
+
+fn decode_info(id: String, bytes: &[u8]) -> Result<AccountInfo, LedgerError> {
+    let value: (u64, String) = match bincode::serde::deserialize(bytes) {
+        Ok(value) => value,
+        Err(_) => return Err(LedgerError::Codec("account info")),
+    };
+    Ok(AccountInfo { id: id, created: value.0, metadata: value.1 })
+}
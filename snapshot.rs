@@ -0,0 +1,246 @@
+extern crate exonum_rocksdb;
+extern crate bincode;
+
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::result::Result;
+use self::exonum_rocksdb::{DB, Options, WriteOptions, WriteBatch};
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::{self, ledger_meta_key};
+use super::journal;
+use super::recovery::{self, Intent};
+
+//on-disk checkpoints of the ledger. exonum_rocksdb has no binding of RocksDB's Checkpoint, so a checkpoint is a copy
+//of every key read from one db snapshot into a new db, which is consistent and can be opened like the token storage.
+//checkpoints live under a root dir: <root>/<name>/ holds the db and <root>/<name>.meta its metadata. the metadata
+//is written last, a checkpoint without it was not completed and is not listed, recovery removes it on the next start.
+
+//copied keys are written in batches of this many.
+const COPY_BATCH_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckpointInfo {
+    pub name: String,
+    //next journal sequence number, every record before it is in the checkpoint.
+    pub seq: usize,
+    //seconds since unix epoch.
+    pub timestamp: u64,
+    //(asset, TotalSupply) of every initialized asset.
+    pub total_supply: Vec<(String, usize)>,
+    pub keys: usize,
+}
+
+pub fn create_checkpoint(db: &Ledger, root: &str, name: &String) -> Result<CheckpointInfo, LedgerError> {
+    check_name(name)?;
+
+    let dir = Path::new(root).join(name);
+    if dir.exists() || meta_path(root, name).exists() {
+        return Err(LedgerError::InvalidInput("checkpoint exists already"));
+    }
+
+    //the intent is recorded before the snapshot is taken, it is the only key of the ledger left out of the copy.
+    let intent_dir = dir.to_string_lossy().into_owned();
+    let _intent_lock = recovery::begin_intent(db, &Intent::CreateCheckpoint(intent_dir))?;
+    match copy_checkpoint(db, root, name, &dir) {
+        Ok(info) => {
+            recovery::end_intent(db)?;
+            println!("Checkpoint '{}' is created with {} keys.", name, info.keys);
+            Ok(info)
+        },
+        Err(e) => {
+            //rolled back here like recovery would on the next start, so the ledger is not left with the intent.
+            //if the cleanup fails too, the intent is kept and recovery retries it.
+            let meta_removed = is_removed(fs::remove_file(meta_path(root, name)));
+            if is_removed(fs::remove_dir_all(&dir)) && meta_removed {
+                if let Err(end_error) = recovery::end_intent(db) {
+                    eprintln!("intent of checkpoint '{}' is left for recovery: {}", name, end_error);
+                }
+            }
+            Err(e)
+        },
+    }
+}
+
+//copy the ledger into dir and write the metadata last, the caller holds the intent.
+fn copy_checkpoint(db: &Ledger, root: &str, name: &String, dir: &Path) -> Result<CheckpointInfo, LedgerError> {
+    let intent_key = ledger_meta_key(keys::INTENT);
+    let snapshot = db.snapshot();
+    let seq: usize = match snapshot.get(&ledger_meta_key(keys::JOURNAL_SEQ))? {
+        Some(bytes) => {
+            match bincode::serde::deserialize(&bytes) {
+                Ok(seq) => seq,
+                Err(_) => return Err(LedgerError::Codec("journal sequence number")),
+            }
+        },
+        None => 0,
+    };
+
+    let mut total_supply: Vec<(String, usize)> = Vec::new();
+    let mut iter = snapshot.raw_iterator();
+    iter.seek(keys::META_PREFIX);
+    while iter.valid() {
+        let key = iter.key().unwrap();
+        if !key.starts_with(keys::META_PREFIX) {
+            break;
+        }
+
+        //m:<asset>:TotalSupply, ledger meta has no asset segment.
+        let name = &key[keys::META_PREFIX.len()..];
+        let suffix = [&[keys::SEPARATOR][..], keys::TOTAL_SUPPLY.as_bytes()].concat();
+        if name.ends_with(&suffix) {
+            let asset = String::from_utf8_lossy(&name[..name.len() - suffix.len()]).into_owned();
+            match bincode::serde::deserialize(&iter.value().unwrap()) {
+                Ok(value) => total_supply.push((asset, value)),
+                Err(_) => return Err(LedgerError::Codec("total supply")),
+            }
+        }
+        iter.next();
+    }
+
+    println!("Going to create checkpoint '{}' at journal seq {}.", name, seq);
+    fs::create_dir_all(root)?;
+    let checkpoint = open_new_db(dir)?;
+    let mut copied: usize = 0;
+    let mut batch = WriteBatch::default();
+    let mut iter = snapshot.raw_iterator();
+    iter.seek_to_first();
+    while iter.valid() {
+        let key = iter.key().unwrap();
+        if key == intent_key {
+            iter.next();
+            continue;
+        }
+
+        //merged values are read, the copy holds plain puts only.
+        batch.put(&key, &iter.value().unwrap())?;
+        copied += 1;
+        if copied % COPY_BATCH_SIZE == 0 {
+            checkpoint.write(batch)?;
+            batch = WriteBatch::default();
+        }
+        iter.next();
+    }
+    //the synced last write makes the whole copy durable before the metadata marks it complete.
+    let mut synced = WriteOptions::default();
+    synced.set_sync(true);
+    checkpoint.write_opt(batch, &synced)?;
+
+    let info = CheckpointInfo {
+        name: name.clone(),
+        seq: seq,
+        timestamp: journal::now_secs(),
+        total_supply: total_supply,
+        keys: copied,
+    };
+    let value = bincode::serde::serialize(&(info.seq, info.timestamp, &info.total_supply, info.keys), bincode::SizeLimit::Infinite).unwrap();
+    let mut meta = File::create(meta_path(root, name))?;
+    meta.write_all(&value)?;
+    meta.sync_all()?;
+    Ok(info)
+}
+
+//every completed checkpoint under root, oldest first.
+pub fn list_checkpoints(root: &str) -> Result<Vec<CheckpointInfo>, LedgerError> {
+    if !Path::new(root).exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut checkpoints: Vec<CheckpointInfo> = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |extension| extension != "meta") {
+            continue;
+        }
+
+        match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(name) => checkpoints.push(checkpoint_info(root, &name.to_string())?),
+            None => continue,
+        }
+    }
+    checkpoints.sort_by(|a, b| (a.timestamp, &a.name).cmp(&(b.timestamp, &b.name)));
+    Ok(checkpoints)
+}
+
+pub fn checkpoint_info(root: &str, name: &String) -> Result<CheckpointInfo, LedgerError> {
+    check_name(name)?;
+
+    let mut bytes: Vec<u8> = Vec::new();
+    match File::open(meta_path(root, name)) {
+        Ok(mut file) => file.read_to_end(&mut bytes)?,
+        Err(_) => return Err(LedgerError::InvalidInput("no such checkpoint")),
+    };
+
+    let value: (usize, u64, Vec<(String, usize)>, usize) = match bincode::serde::deserialize(&bytes) {
+        Ok(value) => value,
+        Err(_) => return Err(LedgerError::Codec("checkpoint metadata")),
+    };
+    Ok(CheckpointInfo { name: name.clone(), seq: value.0, timestamp: value.1, total_supply: value.2, keys: value.3 })
+}
+
+//copy checkpoint name into a new db at target, returns the number of keys restored.
+//the token storage itself is never overwritten, stop the ledger and move target in place of it.
+pub fn restore_checkpoint(root: &str, name: &String, target: &str) -> Result<usize, LedgerError> {
+    let info = checkpoint_info(root, name)?;
+
+    let target = Path::new(target);
+    if target.exists() && fs::read_dir(target)?.next().is_some() {
+        return Err(LedgerError::InvalidInput("restore target is not empty"));
+    }
+
+    println!("Going to restore checkpoint '{}' of journal seq {} to {}.", name, info.seq, target.display());
+    let checkpoint = DB::open(&Options::default(), Path::new(root).join(name))?;
+    let restored = open_new_db(target)?;
+    let mut copied: usize = 0;
+    let mut batch = WriteBatch::default();
+    let mut iter = checkpoint.raw_iterator();
+    iter.seek_to_first();
+    while iter.valid() {
+        batch.put(&iter.key().unwrap(), &iter.value().unwrap())?;
+        copied += 1;
+        if copied % COPY_BATCH_SIZE == 0 {
+            restored.write(batch)?;
+            batch = WriteBatch::default();
+        }
+        iter.next();
+    }
+    restored.write(batch)?;
+
+    if copied != info.keys {
+        return Err(LedgerError::Codec("checkpoint, key count does not match its metadata"));
+    }
+
+    println!("Checkpoint '{}' is restored to {} with {} keys.", name, target.display(), copied);
+    Ok(copied)
+}
+
+//names become file names under root.
+fn check_name(name: &String) -> Result<(), LedgerError> {
+    if name.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty checkpoint name"));
+    }
+
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(LedgerError::InvalidInput("checkpoint name can only contain letters, digits, '-' and '_'"));
+    }
+    Ok(())
+}
+
+//a file that was never created counts as removed.
+fn is_removed(result: ::std::io::Result<()>) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(ref e) => e.kind() == ErrorKind::NotFound,
+    }
+}
+
+fn meta_path(root: &str, name: &String) -> ::std::path::PathBuf {
+    Path::new(root).join(format!("{}.meta", name))
+}
+
+fn open_new_db(dir: &Path) -> Result<DB, LedgerError> {
+    let mut opts = Options::default();
+    opts.create_if_missing(true);
+    Ok(DB::open(&opts, dir)?)
+}
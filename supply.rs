@@ -0,0 +1,234 @@
+extern crate exonum_rocksdb;
+
+use std::result::Result;
+use self::exonum_rocksdb::WriteBatch;
+use super::error::LedgerError;
+use super::ledger::Ledger;
+use super::keys::{self, account_key, meta_key};
+use super::lock::lock_keys;
+use super::token_set::TokenSet;
+use super::owner_index;
+use super::auth;
+use super::freeze::check_not_frozen;
+use super::journal::{self, JournalKind};
+use super::account::{get_super_account_id, check_super_account, get_super_account_value, get_burned, usize_bytes,
+                     load_account, take_amount, take_token_ids};
+
+//create `amount` new tokens for super account, only super account can mint.
+//new ids are appended after every id ever issued, so they never collide with burned ones.
+pub fn mint(db: &Ledger, asset: &String, account: &String, amount: usize) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
+
+    if amount == 0 {
+        return Err(LedgerError::InvalidInput("amount to mint must be greater than zero"));
+    }
+
+    if get_super_account_id(db, asset)? != *account {
+        return Err(LedgerError::PermissionDenied(account.clone()));
+    }
+
+    //minting carries no signature either, a super account with a public key cannot mint through the operator.
+    auth::check_unsigned(db, asset, account)?;
+
+    let _locks = lock_keys(db, vec![meta_key(asset, keys::SUPER_BALANCE)])?;
+    check_super_account(db, asset, account)?;
+
+    let super_balance: usize = get_super_account_value(db, asset, keys::SUPER_BALANCE)?;
+    let super_total_supply: usize = get_super_account_value(db, asset, keys::TOTAL_SUPPLY)?;
+    let issued_end = super_total_supply + get_burned(db, asset)?;
+
+    //ids are usize, a wrapped sum would hand out ids that were issued already.
+    let (total_supply, super_balance, minted_end) = match (super_total_supply.checked_add(amount),
+                                                           super_balance.checked_add(amount),
+                                                           issued_end.checked_add(amount)) {
+        (Some(total_supply), Some(super_balance), Some(minted_end)) => (total_supply, super_balance, minted_end),
+        _ => return Err(LedgerError::InvalidInput("amount to mint is too large")),
+    };
+
+    println!("Going to mint {} tokens to super account '{}'", amount, account);
+    let mut batch = WriteBatch::default();
+    batch.put(&meta_key(asset, keys::TOTAL_SUPPLY), &usize_bytes(total_supply))?;
+    batch.put(&meta_key(asset, keys::SUPER_BALANCE), &usize_bytes(super_balance))?;
+    let minted = TokenSet::from_range(issued_end, minted_end);
+    let _seq_lock = journal::append(db, &mut batch, JournalKind::Mint, asset, &String::new(), account, &minted, None)?;
+    db.write(batch)?;
+
+    println!("Total supply is {} now.", total_supply);
+    Ok(())
+}
+
+//destroy `amount` tokens of an account. super account burns the lowest ids of its tail block,
+//end users burn their last tokens like a transfer would move them.
+pub fn burn(db: &Ledger, asset: &String, account: &String, amount: usize) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
+
+    if account.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty account id"));
+    }
+
+    if amount == 0 {
+        return Err(LedgerError::InvalidInput("amount to burn must be greater than zero"));
+    }
+
+    //burning carries no signature, an account with a public key cannot be burned by the operator.
+    auth::check_unsigned(db, asset, account)?;
+
+    if get_super_account_id(db, asset)? == *account {
+        return burn_super_tokens(db, asset, account, amount);
+    }
+
+    let _locks = lock_keys(db, vec![meta_key(asset, keys::SUPER_BALANCE), account_key(asset, account)])?;
+    check_not_frozen(db, asset, account)?;
+
+    let mut balance = load_account(db, asset, account)?;
+    let burned_tokens = take_amount(&mut balance, account, amount)?;
+    commit_end_user_burn(db, asset, account, &balance, &burned_tokens)
+}
+
+//destroy exactly the listed token ids of an end user account.
+pub fn burn_tokens(db: &Ledger, asset: &String, account: &String, token_ids: &[usize]) -> Result<(), LedgerError> {
+    keys::check_asset(asset)?;
+
+    if account.len() == 0 {
+        return Err(LedgerError::InvalidInput("empty account id"));
+    }
+
+    if token_ids.len() == 0 {
+        return Err(LedgerError::InvalidInput("no token id to burn"));
+    }
+
+    //same as transfer_tokens, picking ids out of super account's tail block would leave holes.
+    if get_super_account_id(db, asset)? == *account {
+        return Err(LedgerError::InvalidInput("use burn with an amount for super account"));
+    }
+
+    auth::check_unsigned(db, asset, account)?;
+
+    let _locks = lock_keys(db, vec![meta_key(asset, keys::SUPER_BALANCE), account_key(asset, account)])?;
+    check_not_frozen(db, asset, account)?;
+
+    let mut balance = load_account(db, asset, account)?;
+    let burned_tokens = take_token_ids(&mut balance, account, token_ids)?;
+    commit_end_user_burn(db, asset, account, &balance, &burned_tokens)
+}
+
+fn burn_super_tokens(db: &Ledger, asset: &String, account: &String, amount: usize) -> Result<(), LedgerError> {
+    let _locks = lock_keys(db, vec![meta_key(asset, keys::SUPER_BALANCE)])?;
+    check_super_account(db, asset, account)?;
+
+    let super_balance: usize = get_super_account_value(db, asset, keys::SUPER_BALANCE)?;
+    if super_balance < amount {
+        return Err(LedgerError::InsufficientBalance {
+            account: account.clone(),
+            balance: super_balance,
+            requested: amount,
+        });
+    }
+
+    //shrinking super balance while Burned grows moves the start of the tail block past the burned ids.
+    let issued_end = get_super_account_value(db, asset, keys::TOTAL_SUPPLY)? + get_burned(db, asset)?;
+    let burned_tokens = TokenSet::from_range(issued_end - super_balance, issued_end - super_balance + amount);
+    let mut batch = WriteBatch::default();
+    batch.put(&meta_key(asset, keys::SUPER_BALANCE), &usize_bytes(super_balance - amount))?;
+    put_burned_supply(db, &mut batch, asset, amount)?;
+    let _seq_lock = journal::append(db, &mut batch, JournalKind::Burn, asset, account, &String::new(), &burned_tokens, None)?;
+    db.write(batch)?;
+
+    println!("Super account '{}' burned {} tokens.", account, amount);
+    Ok(())
+}
+
+//atomic commit: overwrite the account's remaining tokens, drop the burned ones from the owner index
+//move them from TotalSupply to Burned and journal the burn. caller must hold the locks of super balance and the account.
+fn commit_end_user_burn(db: &Ledger, asset: &String, account: &String, balance: &TokenSet, burned_tokens: &TokenSet) -> Result<(), LedgerError> {
+    let mut batch = WriteBatch::default();
+    batch.put(&account_key(asset, account), &balance.to_bytes())?;
+    owner_index::unindex(db, &mut batch, asset, account, burned_tokens)?;
+    put_burned_supply(db, &mut batch, asset, burned_tokens.len())?;
+    let _seq_lock = journal::append(db, &mut batch, JournalKind::Burn, asset, account, &String::new(), burned_tokens, None)?;
+    db.write(batch)?;
+
+    println!("'{}' burned {} tokens, {} tokens left.", account, burned_tokens.len(), balance.len());
+    Ok(())
+}
+
+fn put_burned_supply(db: &Ledger, batch: &mut WriteBatch, asset: &String, amount: usize) -> Result<(), LedgerError> {
+    let super_total_supply: usize = get_super_account_value(db, asset, keys::TOTAL_SUPPLY)?;
+    let burned: usize = get_burned(db, asset)?;
+    batch.put(&meta_key(asset, keys::TOTAL_SUPPLY), &usize_bytes(super_total_supply - amount))?;
+    batch.put(&meta_key(asset, keys::BURNED), &usize_bytes(burned + amount))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use self::tempdir::TempDir;
+    use super::{mint, burn};
+    use super::super::ledger::open_ledger;
+    use super::super::config::DbConfig;
+    use super::super::error::LedgerError;
+    use super::super::keys::{self, meta_key};
+    use super::super::lock::lock_keys;
+    use super::super::account::{get_super_account_value, get_balance, transfer};
+    use super::super::bootstrap::init_super_account;
+    use super::super::audit::audit;
+
+    #[test]
+    fn mint_refuses_to_overflow_the_supply() {
+        let dir = TempDir::new("mint_overflow").unwrap();
+        let db = open_ledger(dir.path().to_str().unwrap(), &DbConfig::default()).unwrap();
+        let (asset, super_id) = ("default".to_string(), "S".to_string());
+        init_super_account(&db, &asset, &super_id, 1000, None).unwrap();
+
+        assert!(mint(&db, &asset, &super_id, usize::MAX).is_err());
+        assert!(mint(&db, &asset, &super_id, usize::MAX - 999).is_err());
+        let total_supply: usize = get_super_account_value(&db, &asset, keys::TOTAL_SUPPLY).unwrap();
+        let super_balance: usize = get_super_account_value(&db, &asset, keys::SUPER_BALANCE).unwrap();
+        assert_eq!((total_supply, super_balance), (1000, 1000));
+
+        mint(&db, &asset, &super_id, 10).unwrap();
+        let total_supply: usize = get_super_account_value(&db, &asset, keys::TOTAL_SUPPLY).unwrap();
+        assert_eq!(total_supply, 1010);
+        assert!(audit(&db, &asset).unwrap().is_empty());
+    }
+
+    #[test]
+    fn super_account_is_checked_again_under_the_super_balance_lock() {
+        let dir = TempDir::new("super_recheck").unwrap();
+        let db = Arc::new(open_ledger(dir.path().to_str().unwrap(), &DbConfig::default()).unwrap());
+        let (asset, super_id) = ("default".to_string(), "S".to_string());
+        init_super_account(&db, &asset, &super_id, 1000, None).unwrap();
+
+        //every call passes the first check, then waits for the lock while the super account changes under it.
+        let held = lock_keys(&db, vec![meta_key(&asset, keys::SUPER_BALANCE)]).unwrap();
+        let threads: Vec<_> = (0..3).map(|i| {
+            let db = db.clone();
+            thread::spawn(move || {
+                let (asset, super_id) = ("default".to_string(), "S".to_string());
+                match i {
+                    0 => mint(&db, &asset, &super_id, 10),
+                    1 => burn(&db, &asset, &super_id, 10),
+                    _ => transfer(&db, &asset, &super_id, &"a".to_string(), 10, None, None),
+                }
+            })
+        }).collect();
+        thread::sleep(Duration::from_millis(200));
+        db.put(&meta_key(&asset, keys::SUPER_ACCOUNT), b"T").unwrap();
+        drop(held);
+
+        for t in threads {
+            match t.join().unwrap() {
+                Err(LedgerError::PermissionDenied(ref account)) if *account == super_id => {},
+                r => panic!("{:?}", r),
+            }
+        }
+        let super_balance: usize = get_super_account_value(&db, &asset, keys::SUPER_BALANCE).unwrap();
+        assert_eq!(super_balance, 1000);
+        assert!(get_balance(&db, &asset, &"a".to_string()).is_err());
+    }
+}
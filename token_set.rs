@@ -0,0 +1,299 @@
+extern crate bincode;
+
+use std::cmp;
+use std::result::Result;
+use super::error::LedgerError;
+
+//a sorted set of token ids kept as disjoint [start, end) ranges, adjacent ranges are always coalesced.
+//so that a contiguous block of tokens costs the same bytes no matter how many tokens it holds.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TokenSet {
+    ranges: Vec<(usize, usize)>,
+    len: usize,
+}
+
+impl TokenSet {
+    pub fn new() -> TokenSet {
+        TokenSet::default()
+    }
+
+    pub fn from_range(start: usize, end: usize) -> TokenSet {
+        let mut set = TokenSet::new();
+        set.insert_range(start, end);
+        set
+    }
+
+    //number of tokens in the set, not the number of ranges.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn ranges(&self) -> &[(usize, usize)] {
+        &self.ranges
+    }
+
+    //build a set out of single ids, consecutive ids end up in one range.
+    //ranges end after their last id, so the largest usize cannot be in a set.
+    pub fn from_ids(ids: &[usize]) -> Result<TokenSet, LedgerError> {
+        let mut set = TokenSet::new();
+        for &id in ids {
+            match id.checked_add(1) {
+                Some(end) => set.insert_range(id, end),
+                None => return Err(LedgerError::InvalidInput("token id is too large")),
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        match id.checked_add(1) {
+            Some(end) => self.contains_range(id, end),
+            None => false,
+        }
+    }
+
+    //true if every id of [start, end) is in the set.
+    pub fn contains_range(&self, start: usize, end: usize) -> bool {
+        match self.ranges.binary_search_by(|r| r.0.cmp(&start)) {
+            Ok(index) => self.ranges[index].1 >= end,
+            Err(0) => false,
+            Err(index) => self.ranges[index - 1].1 >= end,
+        }
+    }
+
+    //remove [start, end) from the set, the range must be fully contained.
+    pub fn remove_range(&mut self, start: usize, end: usize) -> bool {
+        if start >= end || !self.contains_range(start, end) {
+            return false;
+        }
+
+        let index = match self.ranges.binary_search_by(|r| r.0.cmp(&start)) {
+            Ok(index) => index,
+            Err(index) => index - 1,
+        };
+        let (s, e) = self.ranges[index];
+        let mut rest: Vec<(usize, usize)> = Vec::new();
+        if s < start {
+            rest.push((s, start));
+        }
+        if end < e {
+            rest.push((end, e));
+        }
+
+        self.ranges.splice(index..index + 1, rest);
+        self.len -= end - start;
+        true
+    }
+
+    //add [start, end) into the set, merging it with any overlapping or adjacent ranges.
+    pub fn insert_range(&mut self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+
+        let first = self.ranges.iter().position(|r| r.1 >= start).unwrap_or(self.ranges.len());
+        let mut last = first;
+        let mut lo = start;
+        let mut hi = end;
+
+        while last < self.ranges.len() && self.ranges[last].0 <= end {
+            let (s, e) = self.ranges[last];
+            lo = cmp::min(lo, s);
+            hi = cmp::max(hi, e);
+            self.len -= e - s;
+            last += 1;
+        }
+
+        self.ranges.splice(first..last, Some((lo, hi)));
+        self.len += hi - lo;
+    }
+
+    pub fn union(&mut self, other: &TokenSet) {
+        for &(start, end) in &other.ranges {
+            self.insert_range(start, end);
+        }
+    }
+
+    //take the last `amount` tokens out of the set, splitting the lowest touched range if needed.
+    pub fn split_off_last(&mut self, amount: usize) -> Option<TokenSet> {
+        if amount > self.len {
+            return None;
+        }
+
+        let mut taken: Vec<(usize, usize)> = Vec::new();
+        let mut remaining = amount;
+
+        while remaining > 0 {
+            let (start, end) = self.ranges.pop().unwrap();
+            if end - start <= remaining {
+                remaining -= end - start;
+                taken.push((start, end));
+            } else {
+                self.ranges.push((start, end - remaining));
+                taken.push((end - remaining, end));
+                remaining = 0;
+            }
+        }
+
+        taken.reverse();
+        self.len -= amount;
+        Some(TokenSet { ranges: taken, len: amount })
+    }
+
+    //value layout: 64 bits token count as header, followed by the serialized range list.
+    //the header stays first so balance lookups only need to decode 8 bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = bincode::serde::serialize(&self.len, bincode::SizeLimit::Infinite).unwrap();
+        let mut ranges = bincode::serde::serialize(&self.ranges, bincode::SizeLimit::Infinite).unwrap();
+        bytes.append(&mut ranges);
+        bytes
+    }
+
+    //the ranges must be sorted, non-empty and neither overlap nor touch, and the header must count them,
+    //the set operations rely on it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<TokenSet, LedgerError> {
+        let set = TokenSet::from_bytes_unchecked(bytes)?;
+        let (ranges, len) = (set.ranges, set.len);
+
+        let mut count: usize = 0;
+        let mut previous_end: Option<usize> = None;
+        for &(start, end) in &ranges {
+            if start >= end || previous_end.map_or(false, |previous_end| start <= previous_end) {
+                return Err(LedgerError::Codec("token ranges, they are not sorted and disjoint"));
+            }
+            count = match count.checked_add(end - start) {
+                Some(count) => count,
+                None => return Err(LedgerError::Codec("token ranges, too many tokens")),
+            };
+            previous_end = Some(end);
+        }
+
+        if count != len {
+            return Err(LedgerError::Codec("token set, header does not match its ranges"));
+        }
+        Ok(TokenSet { ranges: ranges, len: len })
+    }
+
+    //decode without checking the ranges, only for audit which reports what exactly is wrong with them.
+    pub(crate) fn from_bytes_unchecked(bytes: &[u8]) -> Result<TokenSet, LedgerError> {
+        let len = balance_from_bytes(bytes)?;
+        let ranges: Vec<(usize, usize)> = match bincode::serde::deserialize(&bytes[8..]) {
+            Ok(ranges) => ranges,
+            Err(_) => return Err(LedgerError::Codec("token ranges")),
+        };
+
+        Ok(TokenSet { ranges: ranges, len: len })
+    }
+}
+
+//read only the token count header of an encoded token set.
+pub fn balance_from_bytes(bytes: &[u8]) -> Result<usize, LedgerError> {
+    if bytes.len() < 8 {
+        return Err(LedgerError::Codec("token set header"));
+    }
+
+    match bincode::serde::deserialize(&bytes[0..8]) {
+        Ok(len) => Ok(len),
+        Err(_) => Err(LedgerError::Codec("token set header")),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::TokenSet;
+
+    #[test]
+    fn insert_coalesces_adjacent_and_overlapping_ranges() {
+        let mut set = TokenSet::from_range(0, 10);
+        set.insert_range(20, 30);
+        set.insert_range(10, 12);
+        assert_eq!(set.ranges(), &[(0, 12), (20, 30)]);
+        assert_eq!(set.len(), 22);
+
+        set.insert_range(5, 25);
+        assert_eq!(set.ranges(), &[(0, 30)]);
+        assert_eq!(set.len(), 30);
+
+        set.insert_range(40, 40);
+        assert_eq!(set.len(), 30);
+    }
+
+    #[test]
+    fn from_ids_joins_consecutive_ids() {
+        let set = TokenSet::from_ids(&[7, 8, 42, 9]).unwrap();
+        assert_eq!(set.ranges(), &[(7, 10), (42, 43)]);
+        assert_eq!(set.len(), 4);
+
+        assert!(TokenSet::from_ids(&[1, ::std::usize::MAX]).is_err());
+    }
+
+    #[test]
+    fn contains_range() {
+        let set = TokenSet::from_ids(&[3, 4, 5, 10]).unwrap();
+        assert!(set.contains_range(3, 6));
+        assert!(set.contains_range(4, 5));
+        assert!(!set.contains_range(2, 4));
+        assert!(!set.contains_range(5, 7));
+        assert!(set.contains(10));
+        assert!(!set.contains(0));
+        assert!(!set.contains(::std::usize::MAX));
+    }
+
+    #[test]
+    fn remove_range_splits_a_range() {
+        let mut set = TokenSet::from_range(0, 10);
+        assert!(set.remove_range(3, 5));
+        assert_eq!(set.ranges(), &[(0, 3), (5, 10)]);
+        assert_eq!(set.len(), 8);
+
+        assert!(!set.remove_range(2, 6));
+        assert!(set.remove_range(0, 3));
+        assert_eq!(set.ranges(), &[(5, 10)]);
+        assert_eq!(set.len(), 5);
+    }
+
+    #[test]
+    fn split_off_last_cuts_the_lowest_touched_range() {
+        let mut set = TokenSet::from_range(0, 10);
+        set.insert_range(20, 25);
+        let taken = set.split_off_last(7).unwrap();
+        assert_eq!(taken.ranges(), &[(8, 10), (20, 25)]);
+        assert_eq!(taken.len(), 7);
+        assert_eq!(set.ranges(), &[(0, 8)]);
+        assert_eq!(set.len(), 8);
+
+        assert!(set.split_off_last(9).is_none());
+        assert_eq!(set.split_off_last(8).unwrap().len(), 8);
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut set = TokenSet::from_range(0, 10);
+        set.insert_range(20, 25);
+        assert_eq!(TokenSet::from_bytes(&set.to_bytes()).unwrap(), set);
+        assert_eq!(TokenSet::from_bytes(&TokenSet::new().to_bytes()).unwrap(), TokenSet::new());
+    }
+
+    #[test]
+    fn from_bytes_rejects_inconsistent_sets() {
+        let set = TokenSet { ranges: vec![(0, 10)], len: 11 };
+        assert!(TokenSet::from_bytes(&set.to_bytes()).is_err());
+
+        let set = TokenSet { ranges: vec![(5, 10), (0, 3)], len: 8 };
+        assert!(TokenSet::from_bytes(&set.to_bytes()).is_err());
+
+        let set = TokenSet { ranges: vec![(0, 5), (5, 10)], len: 10 };
+        assert!(TokenSet::from_bytes(&set.to_bytes()).is_err());
+
+        let set = TokenSet { ranges: vec![(3, 3)], len: 0 };
+        assert!(TokenSet::from_bytes(&set.to_bytes()).is_err());
+
+        assert!(TokenSet::from_bytes(&[1, 2, 3]).is_err());
+    }
+}